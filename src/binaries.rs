@@ -0,0 +1,277 @@
+use crate::{update_checksums_for_removed_paths, CARGO_TOML};
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{builder::PossibleValue, ValueEnum};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// What to do with prebuilt binaries found in the kept crates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StripBinaries {
+    /// Remove them, updating the checksums.
+    Remove,
+    /// Only list them.
+    Report,
+}
+
+impl ValueEnum for StripBinaries {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Remove, Self::Report]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Remove => PossibleValue::new("remove"),
+            Self::Report => PossibleValue::new("report"),
+        })
+    }
+}
+
+/// The kinds of prebuilt binaries we detect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BinaryKind {
+    Elf,
+    Pe,
+    MachO,
+    /// Static libraries, including MSVC `.lib` files.
+    Ar,
+}
+
+impl std::fmt::Display for BinaryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Elf => "ELF",
+            Self::Pe => "PE",
+            Self::MachO => "Mach-O",
+            Self::Ar => "ar archive",
+        })
+    }
+}
+
+/// The number of bytes read from each file to detect binaries.
+const HEADER_LEN: u64 = 4096;
+
+/// Detect a binary from the start of a file, using its magic number.
+fn detect_binary(buf: &[u8]) -> Option<BinaryKind> {
+    // Offsets are read from the file, so they must not overflow
+    let u32_at = |offset: usize| -> Option<[u8; 4]> {
+        buf.get(offset..offset.checked_add(4)?)?.try_into().ok()
+    };
+    if buf.starts_with(b"\x7fELF") {
+        return Some(BinaryKind::Elf);
+    }
+    if buf.starts_with(b"!<arch>\n") || buf.starts_with(b"!<thin>\n") {
+        return Some(BinaryKind::Ar);
+    }
+    match u32_at(0)? {
+        [0xfe, 0xed, 0xfa, 0xce | 0xcf] | [0xce | 0xcf, 0xfa, 0xed, 0xfe] => {
+            return Some(BinaryKind::MachO)
+        }
+        // Universal binaries share their magic number with Java class files, which
+        // have a version of at least 45 where these have a small architecture count.
+        [0xca, 0xfe, 0xba, 0xbe] if u32_at(4).is_some_and(|n| u32::from_be_bytes(n) < 45) => {
+            return Some(BinaryKind::MachO)
+        }
+        _ => {}
+    }
+    // A DOS header pointing to the PE signature
+    if buf.starts_with(b"MZ") {
+        let offset = u32::from_le_bytes(u32_at(0x3c)?) as usize;
+        if u32_at(offset) == Some(*b"PE\0\0") {
+            return Some(BinaryKind::Pe);
+        }
+    }
+    None
+}
+
+/// Find the prebuilt binaries in a crate, returning their paths relative to it.
+fn find_binaries(path: &Utf8Path) -> Result<Vec<(Utf8PathBuf, BinaryKind)>> {
+    let mut r = Vec::new();
+    let mut buf = Vec::new();
+    for entry in walkdir::WalkDir::new(path).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        buf.clear();
+        std::fs::File::open(entry.path())
+            .and_then(|f| f.take(HEADER_LEN).read_to_end(&mut buf))
+            .with_context(|| format!("Reading {:?}", entry.path()))?;
+        if let Some(kind) = detect_binary(&buf) {
+            let rel = Utf8Path::from_path(entry.path().strip_prefix(path)?)
+                .ok_or_else(|| anyhow::anyhow!("Invalid non-UTF8 path: {:?}", entry.path()))?;
+            r.push((rel.to_path_buf(), kind));
+        }
+    }
+    Ok(r)
+}
+
+/// The part of a vendored crate's manifest naming its build script.
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    package: ManifestPackage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestPackage {
+    build: Option<toml::Value>,
+}
+
+/// Return the path of the build script of a crate, if any.
+fn build_script(path: &Utf8Path) -> Result<Option<Utf8PathBuf>> {
+    let manifest_path = path.join(CARGO_TOML);
+    let buf = std::fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Reading {manifest_path}"))?;
+    let manifest: Manifest =
+        toml::from_str(&buf).with_context(|| format!("Parsing {manifest_path}"))?;
+    let build = match manifest.package.build {
+        Some(toml::Value::String(build)) => Some(Utf8PathBuf::from(build)),
+        Some(toml::Value::Boolean(false)) => None,
+        // Cargo uses `build.rs` by default
+        _ => Some(Utf8PathBuf::from("build.rs")).filter(|b| path.join(b).exists()),
+    };
+    Ok(build)
+}
+
+/// Returns true if the source mentions the path of the binary, or its file name as
+/// a string literal; a bare file name like `lib.a` often occurs in unrelated strings.
+fn references(source: &str, binary: &Utf8Path) -> bool {
+    let has_dir = binary.parent().is_some_and(|p| !p.as_str().is_empty());
+    (has_dir && source.contains(binary.as_str()))
+        || binary
+            .file_name()
+            .is_some_and(|n| source.contains(&format!("\"{n}\"")))
+}
+
+/// Scan the kept crates for prebuilt binaries, and remove or list them.
+/// Fails if a binary to remove is referenced by the build script of its crate.
+pub(crate) fn strip_binaries<'a>(
+    output_dir: &Utf8Path,
+    dirs: impl Iterator<Item = &'a str>,
+    mode: StripBinaries,
+) -> Result<()> {
+    // The binaries in each crate, along with the build script referencing them
+    let mut found: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+    for dir in dirs {
+        let path = output_dir.join(dir);
+        let binaries = find_binaries(&path)?;
+        if binaries.is_empty() {
+            continue;
+        }
+        let build = build_script(&path)?;
+        // The build script may have been removed by an exclude
+        let source = build
+            .as_ref()
+            .and_then(|b| std::fs::read_to_string(path.join(b)).ok());
+        let crate_binaries = found.entry(dir).or_default();
+        for (binary, kind) in binaries {
+            let referenced = source.as_deref().is_some_and(|s| references(s, &binary));
+            crate_binaries.push((binary, kind, build.clone().filter(|_| referenced)));
+        }
+    }
+    if found.is_empty() {
+        return Ok(());
+    }
+
+    match mode {
+        StripBinaries::Report => {
+            eprintln!("Prebuilt binaries:");
+            for (dir, binaries) in found.iter() {
+                for (binary, kind, build) in binaries {
+                    match build {
+                        Some(build) => {
+                            eprintln!("  {dir}: {binary} ({kind}, referenced by {build})")
+                        }
+                        None => eprintln!("  {dir}: {binary} ({kind})"),
+                    }
+                }
+            }
+        }
+        StripBinaries::Remove => {
+            let referenced: Vec<_> = found
+                .iter()
+                .flat_map(|(dir, binaries)| {
+                    binaries.iter().filter_map(move |(binary, _, build)| {
+                        build
+                            .as_ref()
+                            .map(|build| format!("{dir}/{binary} ({build})"))
+                    })
+                })
+                .collect();
+            if !referenced.is_empty() {
+                anyhow::bail!(
+                    "Prebuilt binaries referenced by build scripts: {}",
+                    referenced.join(", ")
+                );
+            }
+            let mut count = 0;
+            for (dir, binaries) in found.iter() {
+                let path = output_dir.join(dir);
+                let mut removed = Vec::new();
+                for (binary, kind, _) in binaries {
+                    let full_path = path.join(binary);
+                    std::fs::remove_file(&full_path)
+                        .with_context(|| format!("Failed to remove file: {full_path}"))?;
+                    eprintln!("Removed binary from crate {dir}: {binary} ({kind})");
+                    removed.push(binary.clone());
+                }
+                update_checksums_for_removed_paths(&path, &removed)?;
+                count += removed.len();
+            }
+            eprintln!(
+                "Removed {count} prebuilt binaries from {} crates",
+                found.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_binary() {
+        let mut pe = b"MZ".to_vec();
+        pe.resize(0x3c, 0);
+        pe.extend(0x80u32.to_le_bytes());
+        pe.resize(0x80, 0);
+        pe.extend(b"PE\0\0");
+        // The PE signature offset must not overflow
+        let mut bad_offset = b"MZ".to_vec();
+        bad_offset.resize(0x3c, 0);
+        bad_offset.extend(u32::MAX.to_le_bytes());
+        let cases: &[(&[u8], Option<BinaryKind>)] = &[
+            (b"\x7fELF\x02\x01\x01", Some(BinaryKind::Elf)),
+            (b"!<arch>\n/               ", Some(BinaryKind::Ar)),
+            (&pe, Some(BinaryKind::Pe)),
+            (&[0xcf, 0xfa, 0xed, 0xfe, 0x07], Some(BinaryKind::MachO)),
+            (
+                &[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 2],
+                Some(BinaryKind::MachO),
+            ),
+            // A Java class file
+            (&[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52], None),
+            (b"MZ is not enough", None),
+            (&bad_offset, None),
+            (b"fn main() {}\n", None),
+            (b"", None),
+        ];
+        for (buf, expected) in cases {
+            assert_eq!(detect_binary(buf), *expected, "{buf:?}");
+        }
+    }
+
+    #[test]
+    fn test_references() {
+        let source = r#"println!("cargo:rustc-link-search=native=lib"); cc::Build::new().object("pregenerated/aes.o").object("test.o"); let asm = "x86_64.asm";"#;
+        assert!(references(source, Utf8Path::new("pregenerated/aes.o")));
+        assert!(!references(source, Utf8Path::new("other/aes.o")));
+        assert!(references(source, Utf8Path::new("lib/test.o")));
+        assert!(!references(source, Utf8Path::new("x86_64.a")));
+        assert!(!references(source, Utf8Path::new("lib/libwindows.a")));
+    }
+}
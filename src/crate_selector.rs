@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use cargo_metadata::semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Selects crates by name, optionally restricted to versions matching a
+/// semver requirement; e.g. `windows_*`, `libz-sys@^1.1` or `openssl-src@=300.*`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct CrateSelector {
+    /// The selector as written
+    repr: String,
+    name: glob::Pattern,
+    version: Option<VersionReq>,
+}
+
+impl CrateSelector {
+    /// Returns true if the selector matches the crate with this name and version.
+    pub(crate) fn matches(&self, name: &str, version: &str) -> bool {
+        if !self.name.matches(name) {
+            return false;
+        }
+        match self.version.as_ref() {
+            None => true,
+            Some(req) => Version::parse(version).is_ok_and(|v| req.matches(&v)),
+        }
+    }
+
+    /// Returns true if the selector names a single crate, rather than a pattern.
+    pub(crate) fn is_specific(&self) -> bool {
+        glob::Pattern::escape(self.name.as_str()) == self.name.as_str()
+    }
+}
+
+impl FromStr for CrateSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, version) = match s.split_once('@') {
+            Some((name, req)) => {
+                let req = VersionReq::parse(req).with_context(|| {
+                    format!("Invalid version requirement in crate selector: {s}")
+                })?;
+                (name, Some(req))
+            }
+            None => (s, None),
+        };
+        if name.is_empty() {
+            anyhow::bail!("Missing crate name in crate selector: {s}");
+        }
+        let name = glob::Pattern::new(name)
+            .with_context(|| format!("Invalid crate pattern in crate selector: {s}"))?;
+        Ok(Self {
+            repr: s.to_string(),
+            name,
+            version,
+        })
+    }
+}
+
+impl TryFrom<String> for CrateSelector {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for CrateSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.repr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crate_selector() {
+        let selector = |s: &str| s.parse::<CrateSelector>().unwrap();
+        assert!(selector("libz-sys").matches("libz-sys", "1.1.8"));
+        assert!(!selector("libz-sys").matches("libz-ng-sys", "1.1.8"));
+        assert!(selector("*").matches("hex", "0.4.3"));
+        assert!(selector("windows_*").matches("windows_x86_64_msvc", "0.52.6"));
+        assert!(!selector("windows_*").matches("windows-sys", "0.52.0"));
+        assert!(selector("libz-sys@^1.1").matches("libz-sys", "1.1.8"));
+        assert!(!selector("libz-sys@^1.1").matches("libz-sys", "1.0.25"));
+        assert!(selector("openssl-src@=300.*").matches("openssl-src", "300.2.1+3.2.0"));
+        assert!(!selector("openssl-src@=300.*").matches("openssl-src", "111.28.2+1.1.1w"));
+        assert!(selector("*-sys@>=0.2, <0.3").matches("libc-sys", "0.2.1"));
+        assert!(selector("libz-sys@^1.1").is_specific());
+        assert!(!selector("windows_*").is_specific());
+        assert_eq!(selector("libz-sys@^1.1").to_string(), "libz-sys@^1.1");
+        assert!("libz-sys@".parse::<CrateSelector>().is_err());
+        assert!("@1.0".parse::<CrateSelector>().is_err());
+        assert!("libz-sys@^x".parse::<CrateSelector>().is_err());
+        assert!("[libz".parse::<CrateSelector>().is_err());
+    }
+}
@@ -1,12 +1,6 @@
-use crate::{Args, VendorFilter};
-use anyhow::{Context, Result};
-use camino::Utf8Path;
+use cargo_metadata::DependencyKind;
 use clap::{builder::PossibleValue, ValueEnum};
 use serde::{Deserialize, Serialize};
-use std::{
-    borrow::Cow,
-    collections::{HashMap, HashSet},
-};
 
 /// Kinds of dependencies that shall be included.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -56,143 +50,99 @@ impl std::fmt::Display for DepKinds {
     }
 }
 
-/// Filter out unwanted dependency kinds.
-///
-/// Replicates logic from add_packages_for_platform() but uses cargo tree
-/// because cargo metadata does not implement dependency kinds filtering.
-/// Ref: <https://github.com/rust-lang/cargo/issues/10718>
-/// Cargo tree is NOT intended for automatic processing so this function
-/// explicitly does not replace the add_packages_for_platform() entirely.
-pub(crate) fn filter_dep_kinds(
-    args: &Args,
-    config: &VendorFilter,
-    packages: &mut HashMap<cargo_metadata::PackageId, &cargo_metadata::Package>,
-    platform: Option<&str>,
-) -> Result<()> {
-    // exit early when no dependency kinds filtering is requested
-    match config.keep_dep_kinds {
-        None | Some(DepKinds::All) => return Ok(()),
-        Some(_) => (),
-    };
-
-    let required_packages = get_required_packages(
-        &args.get_all_manifest_paths(),
-        args.offline,
-        config,
-        platform,
-    )?;
-
-    packages.retain(|_, package| {
-        required_packages.contains(&(
-            Cow::Borrowed(&package.name),
-            Cow::Borrowed(&package.version),
-        ))
-    });
-    Ok(())
-}
-
-/// Returns the set of required packages to satisfy filters specified in config
-fn get_required_packages<'a>(
-    manifest_paths: &[Option<&Utf8Path>],
-    offline: bool,
-    config: &VendorFilter,
-    platform: Option<&str>,
-) -> Result<HashSet<(Cow<'a, str>, Cow<'a, cargo_metadata::semver::Version>)>> {
-    let keep_dep_kinds = config.keep_dep_kinds.expect("keep_dep_kinds not set");
-    let mut required_packages = HashSet::new();
-    for manifest_path in manifest_paths {
-        let mut cargo_tree = std::process::Command::new("cargo");
-        cargo_tree
-            .arg("tree")
-            .args(["--quiet", "--prefix", "none"]) // ignore non-relevant output
-            .args(["--edges", &keep_dep_kinds.to_string()]); // key filter not available with metadata
-        if offline {
-            cargo_tree.arg("--offline");
-        }
-        if let Some(manifest_path) = manifest_path {
-            cargo_tree.args(["--manifest-path", manifest_path.as_str()]);
-        }
-        if config.all_features {
-            cargo_tree.arg("--all-features");
-        }
-        if config.no_default_features {
-            cargo_tree.arg("--no-default-features");
-        }
-        if !config.features.is_empty() {
-            cargo_tree.arg("--features").args(&config.features);
-        }
-        match platform {
-            Some(platform) => cargo_tree.arg(format!("--target={platform}")),
-            None => {
-                // different than in cargo metadata the default is current platform only
-                cargo_tree.arg("--target=all")
+impl DepKinds {
+    /// Returns true if an edge of the given kind should be followed.
+    pub(crate) fn includes(&self, kind: DependencyKind) -> bool {
+        match kind {
+            DependencyKind::Normal => {
+                matches!(self, Self::All | Self::Normal | Self::NoBuild | Self::NoDev)
             }
-        };
-        let output = cargo_tree.output()?;
-        if !output.status.success() {
-            anyhow::bail!(
-                "Failed to execute cargo tree: {:?}",
-                String::from_utf8(output.stderr).expect("Invalid cargo tree output")
-            );
-        }
-        let output_str = String::from_utf8(output.stdout).expect("Invalid cargo tree output");
-        for line in output_str.lines() {
-            if line.trim().is_empty() {
-                // `cargo tree` output from a `[workspace]` with multiple
-                // `members` will contain blank lines that must be skipped.
-                continue;
+            DependencyKind::Build => {
+                matches!(self, Self::All | Self::Build | Self::NoNormal | Self::NoDev)
             }
-            let tokens: Vec<&str> = line.split(' ').collect();
-            let [package, version, ..] = tokens.as_slice() else {
-                anyhow::bail!("Invalid output received from cargo tree: {line}");
-            };
-            if version.len() < 5 || version.contains("feature") {
-                continue; // skip invalid entries and "feature" list
+            DependencyKind::Development => {
+                matches!(self, Self::All | Self::Dev | Self::NoNormal | Self::NoBuild)
             }
-            // need to remove the initial "v" character that the cargo tree is printing in package name
-            // Ref: <https://doc.rust-lang.org/cargo/commands/cargo-tree.html>
-            // The PR requesting the v to be removed (or configurable) was closed:
-            // <https://github.com/rust-lang/cargo/issues/13120>
-            let version = version
-                .strip_prefix('v')
-                .with_context(|| format!("Invalid version: {}", tokens[1]))?;
-            let version = cargo_metadata::semver::Version::parse(version)
-                .with_context(|| format!("Cannot parse version {version} for {package}"))?;
-            required_packages.insert((Cow::Owned(package.to_string()), Cow::Owned(version)));
+            _ => false,
         }
     }
-    Ok(required_packages)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::resolve::{FeatureSelection, Resolution};
+    use anyhow::{Context, Result};
     use camino::Utf8PathBuf;
-    use serde_json::json;
+    use cargo_metadata::PackageId;
+    use std::collections::HashSet;
+
+    /// Returns the set of packages reachable through edges of the given kinds
+    fn get_required_packages(
+        resolutions: &[Resolution],
+        keep_dep_kinds: DepKinds,
+        platform: Option<&str>,
+    ) -> Result<HashSet<PackageId>> {
+        let mut required_packages = HashSet::new();
+        for resolution in resolutions {
+            let resolved = resolution
+                .exec(platform)
+                .context("Resolving dependency kinds")?;
+            // Follow only edges which have at least one of the requested kinds.
+            let reached = resolved
+                .walk(|_, dep| {
+                    dep.dep_kinds
+                        .iter()
+                        .any(|k| keep_dep_kinds.includes(k.kind))
+                })?
+                .into_packages();
+            required_packages.extend(reached);
+        }
+        Ok(required_packages)
+    }
 
-    #[test]
-    fn test_dep_kind_dev_only() {
+    /// Resolve our own manifest
+    fn own_resolution(features: FeatureSelection) -> Vec<Resolution> {
         let mut own_cargo_toml = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         own_cargo_toml.push("Cargo.toml");
-        let rp = get_required_packages(
-            &[Some(&own_cargo_toml)],
-            false,
-            &serde_json::from_value(json!({ "keep-dep-kinds": "dev"})).unwrap(),
-            Some("x86_64-pc-windows-gnu"),
-        )
-        .unwrap();
+        vec![Resolution::new(Some(&own_cargo_toml), false, features)]
+    }
+
+    #[test]
+    fn test_dep_kinds_includes() {
+        use DependencyKind::{Build, Development, Normal};
+        let cases = [
+            (DepKinds::All, [true, true, true]),
+            (DepKinds::Normal, [true, false, false]),
+            (DepKinds::Build, [false, true, false]),
+            (DepKinds::Dev, [false, false, true]),
+            (DepKinds::NoNormal, [false, true, true]),
+            (DepKinds::NoBuild, [true, false, true]),
+            (DepKinds::NoDev, [true, true, false]),
+        ];
+        for (kinds, expected) in cases {
+            let r = [Normal, Build, Development].map(|k| kinds.includes(k));
+            assert_eq!(r, expected, "{kinds}");
+        }
+    }
+
+    #[test]
+    fn test_dep_kind_dev_only() {
+        let resolutions = own_resolution(FeatureSelection::default());
+        let rp = get_required_packages(&resolutions, DepKinds::Dev, Some("x86_64-pc-windows-gnu"))
+            .unwrap();
         assert_eq!(rp.len(), 3); // own package + once_cell + serial_test dev dependencies
     }
 
     #[test]
     fn test_dep_kind_all_number() {
-        let mut own_cargo_toml = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        own_cargo_toml.push("Cargo.toml");
+        let resolutions = own_resolution(FeatureSelection {
+            all_features: true,
+            ..Default::default()
+        });
         let rp = get_required_packages(
-            &[Some(&own_cargo_toml)],
-            false,
-            &serde_json::from_value(json!({ "keep-dep-kinds": "all", "--all-features": true}))
-                .unwrap(),
+            &resolutions,
+            DepKinds::All,
             None, // all platforms
         )
         .unwrap();
@@ -201,22 +151,19 @@ mod tests {
 
     #[test]
     fn test_dep_kind_normal_vs_no_build() {
-        let mut own_cargo_toml = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        own_cargo_toml.push("Cargo.toml");
+        let resolutions = own_resolution(FeatureSelection::default());
 
         let rp_normal = get_required_packages(
-            &[Some(&own_cargo_toml)],
-            false,
-            &serde_json::from_value(json!({ "keep-dep-kinds": "normal"})).unwrap(),
+            &resolutions,
+            DepKinds::Normal,
             Some("x86_64-pc-windows-gnu"),
         )
         .unwrap();
 
         // no-build => normal + dev dependencies, so including once_call, serial_test...
         let rp_no_build = get_required_packages(
-            &[Some(&own_cargo_toml)],
-            false,
-            &serde_json::from_value(json!({ "keep-dep-kinds": "no-build"})).unwrap(),
+            &resolutions,
+            DepKinds::NoBuild,
             Some("x86_64-pc-windows-gnu"),
         )
         .unwrap();
@@ -232,22 +179,19 @@ mod tests {
 
     #[test]
     fn test_dep_kind_build_vs_no_dev() {
-        let mut own_cargo_toml = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        own_cargo_toml.push("Cargo.toml");
+        let resolutions = own_resolution(FeatureSelection::default());
 
         let rp_build = get_required_packages(
-            &[Some(&own_cargo_toml)],
-            false,
-            &serde_json::from_value(json!({ "keep-dep-kinds": "build"})).unwrap(),
+            &resolutions,
+            DepKinds::Build,
             Some("x86_64-unknown-linux-gnu"),
         )
         .unwrap();
 
         // no-dev => build + normal so the list shall be larger
         let rp_no_dev = get_required_packages(
-            &[Some(&own_cargo_toml)],
-            false,
-            &serde_json::from_value(json!({ "keep-dep-kinds": "no-dev"})).unwrap(),
+            &resolutions,
+            DepKinds::NoDev,
             Some("x86_64-unknown-linux-gnu"),
         )
         .unwrap();
@@ -1,23 +1,27 @@
 use anyhow::{anyhow, Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
-use cargo_metadata::{
-    CargoOpt::{AllFeatures, NoDefaultFeatures, SomeFeatures},
-    MetadataCommand, Package,
-};
+use cargo_metadata::{MetadataCommand, Package};
 use clap::Parser;
-use either::Either;
 use glob::glob;
 use serde::{Deserialize, Serialize};
-use smallvec::SmallVec;
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::{BufReader, Write};
 use std::process::Command;
+use std::rc::Rc;
 use std::vec;
 
 
+mod binaries;
+mod crate_selector;
 mod dep_kinds_filtering;
+mod licenses;
+mod platform_cfg;
+mod presets;
+mod resolve;
+mod target_spec;
 mod tiers;
+mod vendored;
 
 /// The path we use in Cargo.toml i.e. `package.metadata.vendor-filter`
 pub const CONFIG_KEY: &str = "vendor-filter";
@@ -65,21 +69,6 @@ struct CargoChecksums {
     package: Option<String>,
 }
 
-/// The minimal bits of Cargo.toml we need.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-struct CargoManifest {
-    package: CargoPackage,
-    features: BTreeMap<String, Vec<String>>,
-}
-
-/// The minimal bits of the `[package]` section in Cargo.toml we need.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-struct CargoPackage {
-    name: String,
-    version: String,
-    edition: String,
-}
-
 /// Types of tar compression we support; gzip for compatibility, zstd is the modern baseline.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum Compression {
@@ -103,10 +92,35 @@ impl Compression {
     }
 }
 
+/// The contents of the stubs replacing filtered crates.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StubStyle {
+    /// An empty library; the default
+    #[default]
+    Empty,
+    /// A library failing to compile with the reason the crate was removed
+    CompileError,
+}
+
+impl clap::ValueEnum for StubStyle {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Empty, Self::CompileError]
+    }
+
+    fn to_possible_value<'a>(&self) -> Option<clap::builder::PossibleValue> {
+        match self {
+            Self::Empty => Some(clap::builder::PossibleValue::new("empty")),
+            Self::CompileError => Some(clap::builder::PossibleValue::new("compile-error")),
+        }
+    }
+}
+
 /// Output format; the default is a directory.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub enum OutputTarget {
     /// Write to a directory; the default path is `vendor`
+    #[default]
     Dir,
     /// Write to an uncompressed (reproducible) tar archive; the default path is vendor.tar
     Tar,
@@ -116,12 +130,6 @@ pub enum OutputTarget {
     TarZstd,
 }
 
-impl Default for OutputTarget {
-    fn default() -> Self {
-        Self::Dir
-    }
-}
-
 impl clap::ValueEnum for OutputTarget {
     fn value_variants<'a>() -> &'a [Self] {
         &[Self::Dir, Self::Tar, Self::TarGzip, Self::TarZstd]
@@ -137,70 +145,259 @@ impl clap::ValueEnum for OutputTarget {
     }
 }
 
-/// Exclude a file/directory from a crate.
+/// Exclude a file/directory from the crates matching a selector.
 #[derive(PartialEq, Eq, Deserialize, Debug, Hash, Clone)]
 #[serde(rename_all = "kebab-case")]
 struct CrateExclude {
-    name: String,
+    name: crate_selector::CrateSelector,
     exclude: String,
 }
 
 impl CrateExclude {
-    /// Parse a crate exclude in the form `CRATENAME#PATH`.
+    /// Parse a crate exclude in the form `CRATE#PATH`, where `CRATE` is a crate selector.
     fn parse_str(s: &str) -> Result<Self> {
         let (k, v) = s
             .split_once('#')
             .ok_or_else(|| anyhow::anyhow!("Missing '#' in crate exclude"))?;
         Ok(Self {
-            name: k.to_string(),
+            name: k.parse()?,
             exclude: v.to_string(),
         })
     }
 }
 
+/// Keep a file/directory in the crates matching a selector, removing everything
+/// not kept.
+#[derive(PartialEq, Eq, Deserialize, Debug, Hash, Clone)]
+#[serde(rename_all = "kebab-case")]
+struct CrateKeep {
+    name: crate_selector::CrateSelector,
+    keep: String,
+}
+
+impl CrateKeep {
+    /// Parse a crate keep path in the form `CRATE#PATH`, where `CRATE` is a crate selector.
+    fn parse_str(s: &str) -> Result<Self> {
+        let (k, v) = s
+            .split_once('#')
+            .ok_or_else(|| anyhow::anyhow!("Missing '#' in crate keep path"))?;
+        Ok(Self {
+            name: k.parse()?,
+            keep: v.to_string(),
+        })
+    }
+}
+
+/// The path filters applying to a vendored crate.
+#[derive(Debug, Default)]
+struct CratePaths<'a> {
+    /// Paths to remove; patterns starting with `!` exempt paths from removal.
+    excludes: BTreeSet<&'a str>,
+    /// If not empty, paths not matching one of these are removed.
+    keep: BTreeSet<&'a str>,
+    /// Presets of paths to remove, except for those the manifest references.
+    presets: BTreeSet<presets::ExcludePreset>,
+}
+
+/// Paths which are never removed by `keep-crate-paths`, as cargo requires them.
+const ALWAYS_KEPT_PATHS: &[&str] = &["Cargo.toml", CARGO_CHECKSUM];
+
+/// An independently configured selection of platforms, features and dependency
+/// kinds; the crates selected by all groups are kept.
+#[derive(PartialEq, Eq, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+struct FilterGroup {
+    platforms: Option<BTreeSet<String>>,
+    exclude_platforms: Option<BTreeSet<String>>,
+    platform_cfg: Option<String>,
+    host_platforms: Option<BTreeSet<String>>,
+    tier: Option<tiers::TierSelector>,
+    #[serde(default)]
+    all_features: bool,
+    #[serde(default)]
+    no_default_features: bool,
+    #[serde(default)]
+    features: Vec<String>,
+    keep_dep_kinds: Option<dep_kinds_filtering::DepKinds>,
+}
+
 /// The configuration used to filter the set of dependencies.
-#[derive(PartialEq, Eq, Deserialize, Debug, Default)]
+#[derive(PartialEq, Eq, Deserialize, Debug, Default, Clone)]
 #[serde(rename_all = "kebab-case")]
 struct VendorFilter {
     platforms: Option<BTreeSet<String>>,
-    tier: Option<tiers::Tier>,
+    exclude_platforms: Option<BTreeSet<String>>,
+    platform_cfg: Option<String>,
+    host_platforms: Option<BTreeSet<String>>,
+    tier: Option<tiers::TierSelector>,
+    tier_table: Option<Utf8PathBuf>,
     #[serde(default)]
     all_features: bool,
     #[serde(default)]
     no_default_features: bool,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     features: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_feature_sets")]
+    feature_sets: Option<Vec<Vec<String>>>,
+    /// Additional features to enable for platforms matching a pattern
+    #[serde(default)]
+    platform_features: BTreeMap<String, Vec<String>>,
+    /// Feature selections for individual workspace members
+    #[serde(default)]
+    members: BTreeMap<String, resolve::FeatureSelection>,
+    packages: Option<BTreeSet<String>>,
+    exclude_packages: Option<BTreeSet<String>>,
     exclude_crate_paths: Option<HashSet<CrateExclude>>,
+    keep_crate_paths: Option<HashSet<CrateKeep>>,
+    #[serde(default)]
+    strict_excludes: bool,
+    exclude_presets: Option<BTreeSet<presets::ExcludePreset>>,
+    strip_binaries: Option<binaries::StripBinaries>,
+    exclude_crates: Option<BTreeSet<String>>,
+    keep_crates: Option<BTreeSet<String>>,
+    #[serde(default)]
+    keep_crates_dependencies: bool,
     keep_dep_kinds: Option<dep_kinds_filtering::DepKinds>,
+    groups: Option<Vec<FilterGroup>>,
+    allowed_licenses: Option<BTreeSet<String>>,
+    denied_licenses: Option<BTreeSet<String>>,
+    license_policy: Option<licenses::LicensePolicy>,
+    stub_style: Option<StubStyle>,
+}
+
+/// Deserialize `feature-sets`, which resolve nothing if empty.
+fn deserialize_feature_sets<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<Vec<String>>>, D::Error> {
+    let sets = Vec::<Vec<String>>::deserialize(deserializer)?;
+    if sets.is_empty() {
+        return Err(serde::de::Error::custom(
+            "At least one feature set must be specified in feature-sets",
+        ));
+    }
+    Ok(Some(sets))
+}
+
+/// Subcommands which do not vendor anything.
+#[derive(clap::Subcommand, Debug)]
+pub enum Subcommand {
+    /// Print the platform tier table in use
+    PrintTierTable,
 }
 
 #[derive(Parser, Debug, Default)]
 #[command(version, about)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Subcommand>,
+
     /// Only include crates for these targets ('*' wildcards are supported).
     ///
-    /// For example, `x86_64-unknown-linux-gnu`.
+    /// For example, `x86_64-unknown-linux-gnu`.  A path to a custom target
+    /// specification file ending in `.json` may also be used.
     #[arg(long)]
     pub platform: Option<Vec<String>>,
 
-    /// Limit platforms to the provided tier ("1" or "2").
+    /// Remove targets from the platform list ('*' wildcards are supported).
+    ///
+    /// For example, `*-linux-musl*`.  The keyword `host` may be used here and in
+    /// `--platform` for the host of the running rustc.
+    #[arg(long)]
+    pub exclude_platform: Option<Vec<String>>,
+
+    /// Only include crates for targets matching this cfg expression.
+    ///
+    /// For example, `cfg(all(unix, not(target_os = "macos")))`.  It is evaluated
+    /// against `rustc --print cfg` for each known target, and may be combined
+    /// with `--platform` and `--tier`.
+    #[arg(long)]
+    pub platform_cfg: Option<String>,
+
+    /// The host platforms build dependencies and proc-macros are built for when
+    /// filtering by platform.  Defaults to the host of the running rustc.
+    #[arg(long)]
+    pub host_platform: Option<Vec<String>>,
+
+    /// Limit platforms to the provided tier ("1", "2" or "3").  A "-with-host-tools"
+    /// suffix, e.g. "2-with-host-tools", only includes targets with host tools.
     #[arg(long, value_parser)]
-    pub tier: Option<tiers::Tier>,
+    pub tier: Option<tiers::TierSelector>,
+
+    /// Path to a tier table to use instead of the built-in one; see
+    /// the `print-tier-table` subcommand for the table in use.
+    #[arg(long)]
+    pub tier_table: Option<Utf8PathBuf>,
 
     /// Remove files/subdirectories in crates that match an exact path.
-    /// The format is "CRATENAME#PATH". CRATENAME is the name of a crate (without
-    /// a version included) or "*" as a wildcard for all crates. PATH must be a
-    /// relative path, and can name a regular file, symbolic link or a directory.
+    /// The format is "CRATE#PATH". CRATE is a crate name, which may contain `*`
+    /// wildcards (e.g. `windows_*`, or "*" for all crates), optionally followed by
+    /// `@` and a semver requirement restricting the versions (e.g. `libz-sys@^1.1`
+    /// or `openssl-src@=300.*`). PATH must be a relative path, and can name a
+    /// regular file, symbolic link or a directory.
     ///
     /// If the filename matches a directory, it and all its contents will be removed.
     /// For example, `curl-sys#curl` will remove the vendored libcurl C sources
     /// from the `curl-sys` crate.
     /// For example, `*#tests` will remove tests folder from all crates.
     ///
+    /// A PATH starting with `!` exempts the matching files from the other excludes
+    /// of the crate; e.g. `libz-sys#src/zlib` and `libz-sys#!src/zlib/zconf.h`.
+    ///
     /// Nonexistent paths will emit a warning, but are not currently an error.
     #[arg(long)]
     pub exclude_crate_path: Option<Vec<String>>,
 
+    /// Keep only the matching files/subdirectories in crates, removing everything
+    /// else; `Cargo.toml` and the checksums are always kept. The format is the same
+    /// as for `--exclude-crate-path`, e.g. `libz-sys#src/*.rs` and `libz-sys#build.rs`.
+    ///
+    /// Excludes still apply to the kept paths.
+    #[arg(long)]
+    pub keep_crate_path: Option<Vec<String>>,
+
+    /// Fail if a `--exclude-crate-path` or `--keep-crate-path` pattern for a
+    /// specific crate (i.e. without '*' wildcards in the crate name), or an
+    /// `--exclude-crate` pattern, matches nothing.
+    ///
+    /// Unlike the other options, this does not replace the configuration in
+    /// `Cargo.toml`, but also applies to its `exclude-crate-paths`, `keep-crate-paths`
+    /// and `exclude-crates`.
+    #[arg(long, default_value_t = false)]
+    pub strict_excludes: bool,
+
+    /// Remove curated lists of paths from all crates, such as their tests or CI
+    /// configuration. Files referenced by the targets or the `build` key of a
+    /// crate's `Cargo.toml`, or included with `include_str!` or `include_bytes!` by
+    /// its sources, are never removed.
+    #[arg(long)]
+    pub exclude_preset: Option<Vec<presets::ExcludePreset>>,
+
+    /// Scan the kept crates for prebuilt binaries (ELF, PE and Mach-O files, and ar
+    /// archives like `.a` and `.lib` files), detected by their contents, and `remove`
+    /// them or only `report` them. Removing fails if a binary is mentioned by the
+    /// build script of its crate.
+    #[arg(long)]
+    pub strip_binaries: Option<binaries::StripBinaries>,
+
+    /// Replace crates with a stub even if they are required by the selected
+    /// platforms and features ('*' wildcards are supported).
+    ///
+    /// For example, `openssl-src` or `*-src` to drop crates bundling C libraries
+    /// which are instead linked dynamically.
+    #[arg(long)]
+    pub exclude_crate: Option<Vec<String>>,
+
+    /// Always keep these crates in full, even if they are not required by the
+    /// selected platforms, tiers and dependency kinds ('*' wildcards are supported).
+    ///
+    /// Crates listed with `--exclude-crate` are still replaced with stubs.
+    #[arg(long)]
+    pub keep_crate: Option<Vec<String>>,
+
+    /// Also keep the dependencies of the crates selected with `--keep-crate`.
+    #[arg(long, default_value_t = false)]
+    pub keep_crates_dependencies: bool,
+
     /// Path to Cargo.toml
     #[arg(long)]
     pub manifest_path: Option<Utf8PathBuf>,
@@ -220,12 +417,56 @@ pub struct Args {
     #[arg(long, short = 'F')]
     pub features: Vec<String>,
 
+    /// Space or comma separated list of features forming a feature set.
+    /// This flag may be specified multiple times; each feature set is resolved
+    /// separately (in addition to `--features`), and the union of the
+    /// dependencies is kept.
+    #[arg(long)]
+    pub feature_set: Option<Vec<String>>,
+
+    /// Additional features to enable for platforms matching a pattern, in
+    /// the form `PATTERN=FEATURES` where FEATURES is a comma separated list.
+    ///
+    /// For example, `*-linux-gnu=systemd` enables the `systemd` feature
+    /// only when resolving dependencies for Linux GNU targets.
+    #[arg(long)]
+    pub platform_features: Option<Vec<String>>,
+
+    /// Only vendor the dependencies of these workspace members ('*' wildcards
+    /// are supported).  The dependencies of all other members are replaced with stubs.
+    #[arg(long, short = 'p')]
+    pub package: Option<Vec<String>>,
+
+    /// Do not vendor the dependencies of these workspace members, unless
+    /// required by another member ('*' wildcards are supported).
+    #[arg(long)]
+    pub exclude: Option<Vec<String>>,
+
     /// Dependencies kinds you want to keep: normal, build and/or development (dev).
     /// Possible values: all (default), normal, build, dev, no-normal, no-build, no-dev
     /// Ref: <https://doc.rust-lang.org/cargo/reference/specifying-dependencies.html>
     #[arg(long)]
     pub keep_dep_kinds: Option<dep_kinds_filtering::DepKinds>,
 
+    /// Only allow crates whose SPDX license expression is satisfied by these licenses.
+    /// For example, with `MIT` allowed, a crate licensed `MIT OR Apache-2.0` is accepted.
+    #[arg(long)]
+    pub allowed_license: Option<Vec<String>>,
+
+    /// Reject crates whose SPDX license expression cannot be satisfied without these licenses.
+    #[arg(long)]
+    pub denied_license: Option<Vec<String>>,
+
+    /// What to do with crates violating the license policy: `fail` the run (the default),
+    /// or `stub` them out when they are only pulled in through optional dependencies.
+    #[arg(long)]
+    pub license_policy: Option<licenses::LicensePolicy>,
+
+    /// The contents of the stubs replacing filtered crates: `empty` (the default)
+    /// or `compile-error`, which fails to compile naming the reason the crate was removed.
+    #[arg(long)]
+    pub stub_style: Option<StubStyle>,
+
     /// Pick the output format.
     #[arg(long, default_value = "dir")]
     pub format: OutputTarget,
@@ -265,9 +506,18 @@ pub struct Args {
 
 fn filter_manifest(manifest: &mut toml::Value) {
     if let Some(t) = manifest.as_table_mut() {
+        // Keep procedural macros as such, as cargo resolves their dependencies for the host
+        let proc_macro = t
+            .get(LIB)
+            .and_then(|lib| lib.get("proc-macro").or_else(|| lib.get("proc_macro")))
+            .and_then(|v| v.as_bool())
+            .unwrap_or_default();
         let mut libtable = toml::Table::new();
         libtable.insert("path".into(), STUB_LIBRS.into());
-        t.insert("lib".into(), libtable.into());
+        if proc_macro {
+            libtable.insert("proc-macro".into(), true.into());
+        }
+        t.insert(LIB.into(), libtable.into());
         for &k in UNWANTED_MANIFEST_KEYS {
             t.remove(k);
         }
@@ -311,13 +561,21 @@ fn sha256_hexdigest(buf: &[u8]) -> Result<String> {
 /// The generated package will fail to compile, but we're relying on it
 /// not actually being compiled.  Entirely removing the crates would
 /// require editing the dependent crates, which would be more involved.
-fn replace_with_stub(path: &Utf8Path) -> Result<()> {
+/// With [`StubStyle::CompileError`], the library fails to compile with
+/// a message naming the `reason` the crate was removed.
+fn replace_with_stub(path: &Utf8Path, style: StubStyle, reason: &str) -> Result<()> {
     let cargo_toml_path = path.join(CARGO_TOML);
     let cargo_toml_data =
         std::fs::read_to_string(&cargo_toml_path).context("Reading Cargo.toml")?;
     let mut cargo_toml_data: toml::Value =
         toml::from_str(&cargo_toml_data).with_context(|| format!("Parsing {cargo_toml_path}"))?;
     filter_manifest(&mut cargo_toml_data);
+    let name = cargo_toml_data
+        .get(MANIFEST_KEY_PACKAGE)
+        .and_then(|p| p.get("name"))
+        .and_then(|n| n.as_str())
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| path.file_name().unwrap_or_default().to_string());
 
     let checksums_path = path.join(CARGO_CHECKSUM);
     let checksums = std::fs::File::open(&checksums_path).map(BufReader::new)?;
@@ -343,8 +601,12 @@ fn replace_with_stub(path: &Utf8Path) -> Result<()> {
     let cargo_toml_data = toml::to_string(&cargo_toml_data).context("Reserializing manifest")?;
     // An empty Cargo.toml
     writef(Utf8Path::new(CARGO_TOML), cargo_toml_data.as_bytes())?;
-    // And an empty source file
-    writef(Utf8Path::new(STUB_LIBRS), b"")?;
+    // And an empty (or failing) source file
+    let librs = match style {
+        StubStyle::Empty => String::new(),
+        StubStyle::CompileError => stub_compile_error(&name, reason),
+    };
+    writef(Utf8Path::new(STUB_LIBRS), librs.as_bytes())?;
     // Finally, serialize the new checksums
     let mut w = std::fs::File::create(checksums_path).map(std::io::BufWriter::new)?;
     serde_json::to_writer(&mut w, &checksums)?;
@@ -352,10 +614,20 @@ fn replace_with_stub(path: &Utf8Path) -> Result<()> {
     Ok(())
 }
 
+/// The source of a stub library which fails to compile, naming the reason
+/// the crate was removed.
+fn stub_compile_error(name: &str, reason: &str) -> String {
+    let msg = format!("crate {name} was removed by cargo-vendor-filterer: {reason}");
+    // The debug representation of a string is a valid string literal
+    format!("compile_error!({msg:?});\n")
+}
+
 impl VendorFilter {
     /// Returns true if this configuration will filter by platform
     fn enables_platform_filtering(&self) -> bool {
         self.tier.is_some()
+            || self.platform_cfg.is_some()
+            || self.exclude_platforms.is_some()
             || self
                 .platforms
                 .as_ref()
@@ -363,6 +635,107 @@ impl VendorFilter {
                 .unwrap_or_default()
     }
 
+    /// The feature selection for all workspace members without their own.
+    fn feature_selection(&self) -> resolve::FeatureSelection {
+        resolve::FeatureSelection {
+            all_features: self.all_features,
+            no_default_features: self.no_default_features,
+            features: self.features.clone(),
+        }
+    }
+
+    /// The platforms build dependencies and procedural macros are built for;
+    /// by default the host of the running rustc.
+    fn host_platforms(&self) -> Result<Vec<String>> {
+        match self.host_platforms.as_ref() {
+            Some(hosts) if hosts.is_empty() => {
+                anyhow::bail!("At least one platform must be specified in host-platforms")
+            }
+            Some(hosts) => replace_host_keyword(hosts),
+            None => Ok(vec![platform_cfg::rustc_host()?]),
+        }
+    }
+
+    /// Compile the `platform-features` patterns into glob patterns.
+    fn platform_feature_patterns(&self) -> Result<Vec<(glob::Pattern, &[String])>> {
+        self.platform_features
+            .iter()
+            .map(|(pattern, features)| {
+                let pattern = glob::Pattern::new(pattern)
+                    .with_context(|| format!("Invalid platform pattern: {pattern}"))?;
+                Ok((pattern, features.as_slice()))
+            })
+            .collect()
+    }
+
+    /// All features from `platform-features`, regardless of platform.
+    fn all_platform_features(&self) -> Vec<String> {
+        let features: BTreeSet<_> = self.platform_features.values().flatten().collect();
+        features.into_iter().cloned().collect()
+    }
+
+    /// Split the configuration into one per filter group.  Without `groups`,
+    /// this is just the configuration itself.
+    fn groups(&self) -> Result<Vec<Self>> {
+        let Some(groups) = self.groups.as_ref() else {
+            return Ok(vec![self.clone()]);
+        };
+        if groups.is_empty() {
+            anyhow::bail!("At least one group must be specified in groups");
+        }
+        if self.enables_platform_filtering()
+            || self.all_features
+            || self.no_default_features
+            || !self.features.is_empty()
+            || self.keep_dep_kinds.is_some()
+        {
+            anyhow::bail!(
+                "Platform, feature and dependency kind options must be set per group with groups"
+            );
+        }
+        let r = groups
+            .iter()
+            .map(|group| Self {
+                platforms: group.platforms.clone(),
+                exclude_platforms: group.exclude_platforms.clone(),
+                platform_cfg: group.platform_cfg.clone(),
+                // The top-level host platforms apply to groups without their own
+                host_platforms: group
+                    .host_platforms
+                    .clone()
+                    .or_else(|| self.host_platforms.clone()),
+                tier: group.tier.clone(),
+                all_features: group.all_features,
+                no_default_features: group.no_default_features,
+                features: group.features.clone(),
+                keep_dep_kinds: group.keep_dep_kinds,
+                groups: None,
+                ..self.clone()
+            })
+            .collect();
+        Ok(r)
+    }
+
+    /// Compile the `exclude-crates` selectors into glob patterns.
+    fn exclude_crate_patterns(&self) -> Result<Vec<glob::Pattern>> {
+        compile_crate_patterns(self.exclude_crates.as_ref())
+    }
+
+    /// Compile the `keep-crates` selectors into glob patterns.
+    fn keep_crate_patterns(&self) -> Result<Vec<glob::Pattern>> {
+        compile_crate_patterns(self.keep_crates.as_ref())
+    }
+
+    /// Compile the `packages` selectors into glob patterns.
+    fn package_patterns(&self) -> Result<Vec<glob::Pattern>> {
+        compile_crate_patterns(self.packages.as_ref())
+    }
+
+    /// Compile the `exclude-packages` selectors into glob patterns.
+    fn exclude_package_patterns(&self) -> Result<Vec<glob::Pattern>> {
+        compile_crate_patterns(self.exclude_packages.as_ref())
+    }
+
     /// Parse a value from `package.metadata.vendor-filter`.
     fn parse_json(meta: &serde_json::Value) -> Result<Option<Self>> {
         let meta = meta.as_object().and_then(|o| o.get(CONFIG_KEY));
@@ -384,12 +757,30 @@ impl VendorFilter {
     /// Parse the subset of CLI arguments that affect vendor content into a filter.
     fn parse_args(args: &Args) -> Result<Option<Self>> {
         let args_unset = args.platform.is_none()
+            && args.exclude_platform.is_none()
+            && args.platform_cfg.is_none()
+            && args.host_platform.is_none()
             && args.tier.is_none()
+            && args.tier_table.is_none()
             && !args.all_features
             && !args.no_default_features
             && args.features.is_empty()
+            && args.feature_set.is_none()
+            && args.platform_features.is_none()
+            && args.package.is_none()
+            && args.exclude.is_none()
             && args.exclude_crate_path.is_none()
-            && args.keep_dep_kinds.is_none();
+            && args.keep_crate_path.is_none()
+            && args.exclude_preset.is_none()
+            && args.strip_binaries.is_none()
+            && args.exclude_crate.is_none()
+            && args.keep_crate.is_none()
+            && !args.keep_crates_dependencies
+            && args.keep_dep_kinds.is_none()
+            && args.allowed_license.is_none()
+            && args.denied_license.is_none()
+            && args.license_policy.is_none()
+            && args.stub_style.is_none();
         let exclude_crate_paths = args
             .exclude_crate_path
             .as_ref()
@@ -399,22 +790,113 @@ impl VendorFilter {
                     .collect::<Result<HashSet<_>>>()
             })
             .transpose()?;
+        let keep_crate_paths = args
+            .keep_crate_path
+            .as_ref()
+            .map(|v| {
+                v.iter()
+                    .map(|e| CrateKeep::parse_str(e))
+                    .collect::<Result<HashSet<_>>>()
+            })
+            .transpose()?;
+        let platform_features = args
+            .platform_features
+            .iter()
+            .flatten()
+            .map(|v| {
+                let (pattern, features) = v
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Missing '=' in platform features: {v}"))?;
+                let features = features
+                    .split(',')
+                    .filter(|f| !f.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect();
+                Ok((pattern.to_string(), features))
+            })
+            .collect::<Result<_>>()?;
         let r = (!args_unset).then(|| Self {
             platforms: args
                 .platform
                 .as_ref()
                 .map(|x| BTreeSet::from_iter(x.iter().cloned())),
+            exclude_platforms: args
+                .exclude_platform
+                .as_ref()
+                .map(|x| BTreeSet::from_iter(x.iter().cloned())),
+            platform_cfg: args.platform_cfg.clone(),
+            host_platforms: args
+                .host_platform
+                .as_ref()
+                .map(|x| BTreeSet::from_iter(x.iter().cloned())),
             tier: args.tier.clone(),
+            tier_table: args.tier_table.clone(),
             all_features: args.all_features,
             no_default_features: args.no_default_features,
             features: args.features.clone(),
+            feature_sets: args.feature_set.as_ref().map(|sets| {
+                sets.iter()
+                    .map(|set| {
+                        set.split([' ', ','])
+                            .filter(|f| !f.is_empty())
+                            .map(ToOwned::to_owned)
+                            .collect()
+                    })
+                    .collect()
+            }),
+            platform_features,
+            members: BTreeMap::new(),
+            packages: args
+                .package
+                .as_ref()
+                .map(|x| BTreeSet::from_iter(x.iter().cloned())),
+            exclude_packages: args
+                .exclude
+                .as_ref()
+                .map(|x| BTreeSet::from_iter(x.iter().cloned())),
             exclude_crate_paths,
+            keep_crate_paths,
+            strict_excludes: args.strict_excludes,
+            exclude_presets: args
+                .exclude_preset
+                .as_ref()
+                .map(|x| BTreeSet::from_iter(x.iter().copied())),
+            strip_binaries: args.strip_binaries,
+            exclude_crates: args
+                .exclude_crate
+                .as_ref()
+                .map(|x| BTreeSet::from_iter(x.iter().cloned())),
+            keep_crates: args
+                .keep_crate
+                .as_ref()
+                .map(|x| BTreeSet::from_iter(x.iter().cloned())),
+            keep_crates_dependencies: args.keep_crates_dependencies,
             keep_dep_kinds: args.keep_dep_kinds,
+            groups: None,
+            allowed_licenses: args
+                .allowed_license
+                .as_ref()
+                .map(|x| BTreeSet::from_iter(x.iter().cloned())),
+            denied_licenses: args
+                .denied_license
+                .as_ref()
+                .map(|x| BTreeSet::from_iter(x.iter().cloned())),
+            license_policy: args.license_policy,
+            stub_style: args.stub_style,
         });
         Ok(r)
     }
 }
 
+/// Compile a list of crate names (which may contain `*` wildcards) into glob patterns.
+fn compile_crate_patterns(selectors: Option<&BTreeSet<String>>) -> Result<Vec<glob::Pattern>> {
+    selectors
+        .into_iter()
+        .flatten()
+        .map(|s| glob::Pattern::new(s).with_context(|| format!("Invalid crate pattern: {s}")))
+        .collect()
+}
+
 /// Process CLI arguments into a filter.
 fn gather_config(args: &Args) -> Result<Option<VendorFilter>> {
     // Accept config from arguments first in preference to Cargo.toml metadata.
@@ -426,11 +908,17 @@ fn gather_config(args: &Args) -> Result<Option<VendorFilter>> {
     let meta = meta
         .exec()
         .context("Executing cargo metadata (first run)")?;
-    if let Some(root) = meta.root_package() {
-        VendorFilter::parse_json(&root.metadata)
+    let config = if let Some(root) = meta.root_package() {
+        VendorFilter::parse_json(&root.metadata)?
     } else {
-        VendorFilter::parse_json(&meta.workspace_metadata)
-    }
+        VendorFilter::parse_json(&meta.workspace_metadata)?
+    };
+    // `--strict-excludes` only changes how the path options are checked, so
+    // it applies on top of the configuration in Cargo.toml.
+    Ok(config.map(|c| VendorFilter {
+        strict_excludes: c.strict_excludes || args.strict_excludes,
+        ..c
+    }))
 }
 
 /// Find all paths matching a glob pattern within a base directory
@@ -451,59 +939,132 @@ fn find_glob_matches(base_path: &Utf8Path, pattern: &str) -> Result<Vec<Utf8Path
     Ok(matches)
 }
 
-/// Given a crate, remove matching files/directories in excludes.
-fn process_excludes(path: &Utf8PathBuf, name: &str, excludes: &HashSet<&str>) -> Result<()> {
-    let mut matched = false;
-    let mut removed_patterns = Vec::new();
-
-    for &exclude in excludes.iter() {
-        if Utf8Path::new(exclude).is_absolute() {
-            anyhow::bail!("Invalid absolute path in crate exclude {name} {exclude}");
+/// Match a pattern against the contents of a crate, returning the matched paths.
+fn match_crate_paths(
+    path: &Utf8Path,
+    name: &str,
+    kind: &str,
+    pattern: &str,
+) -> Result<Vec<Utf8PathBuf>> {
+    if Utf8Path::new(pattern).is_absolute() {
+        anyhow::bail!("Invalid absolute path in crate {kind} {name} {pattern}");
+    }
+    match find_glob_matches(path, pattern) {
+        Ok(matches) => Ok(matches),
+        Err(e) => {
+            eprintln!("Warning: Failed to process {kind} for crate {name}: {pattern} - {e}");
+            Ok(Vec::new())
         }
-        let paths_to_remove = match find_glob_matches(path, exclude) {
-            Ok(matches) => {
-                if matches.is_empty() {
-                    eprintln!("Warning: No match for exclude for crate {name}: {exclude}");
-                }
-                matches
-            }
-            Err(e) => {
-                eprintln!("Warning: Failed to process exclude for crate {name}: {exclude} - {e}");
-                continue;
-            }
+    }
+}
+
+/// Given a crate, remove the files/directories matching its excludes (unless
+/// negated), and if it has keep paths, everything else.  Returns the patterns
+/// which matched something.
+fn process_crate_paths<'a>(
+    path: &Utf8Path,
+    name: &str,
+    filter: &CratePaths<'a>,
+) -> Result<CratePaths<'a>> {
+    let mut matched = CratePaths::default();
+    let mut excluded = HashSet::new();
+    let mut negated = HashSet::new();
+    for &exclude in filter.excludes.iter() {
+        let (set, pattern) = match exclude.strip_prefix('!') {
+            Some(pattern) => (&mut negated, pattern),
+            None => (&mut excluded, exclude),
         };
+        let paths = match_crate_paths(path, name, "exclude", pattern)?;
+        if !paths.is_empty() {
+            matched.excludes.insert(exclude);
+        }
+        set.extend(paths);
+    }
+    let mut kept = HashSet::new();
+    for &keep in filter.keep.iter() {
+        let paths = match_crate_paths(path, name, "keep path", keep)?;
+        if !paths.is_empty() {
+            matched.keep.insert(keep);
+        }
+        kept.extend(paths);
+    }
+    let mut preset_excluded = HashSet::new();
+    for preset in filter.presets.iter() {
+        for &pattern in preset.patterns() {
+            preset_excluded.extend(match_crate_paths(path, name, "exclude preset", pattern)?);
+        }
+    }
+    // Presets must not break the manifest by removing the files of its targets,
+    // nor the build by removing files included by its sources.
+    let referenced = if filter.presets.is_empty() {
+        HashSet::new()
+    } else {
+        let mut referenced = presets::manifest_referenced_paths(path)?;
+        referenced.extend(presets::included_paths(path)?);
+        referenced
+    };
+    // A path matches if it or one of its parent directories was matched
+    let matches = |set: &HashSet<Utf8PathBuf>, p: &Utf8Path| p.ancestors().any(|a| set.contains(a));
 
-        for relative_path in paths_to_remove {
-            let full_path = path.join(&relative_path);
-            let meta = match full_path.symlink_metadata() {
-                Ok(r) => Ok(Some(r)),
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
-                Err(e) => Err(e),
-            }?;
-            if let Some(meta) = meta {
-                if meta.is_dir() {
-                    std::fs::remove_dir_all(&full_path)
-                        .with_context(|| format!("Failed to remove directory: {}", full_path))?;
-                } else {
-                    std::fs::remove_file(&full_path)
-                        .with_context(|| format!("Failed to remove file: {}", full_path))?;
-                }
-                eprintln!("Removed from crate {name}: {}", relative_path);
-                matched = true;
-                removed_patterns.push(relative_path);
+    let mut entries = Vec::new();
+    for entry in walkdir::WalkDir::new(path).min_depth(1).sort_by_file_name() {
+        let entry = entry?;
+        let rel = Utf8Path::from_path(entry.path().strip_prefix(path)?)
+            .ok_or_else(|| anyhow!("Invalid non-UTF8 path: {:?}", entry.path()))?
+            .to_path_buf();
+        let by_preset = matches(&preset_excluded, &rel) && !referenced.contains(&rel);
+        let excluded = (matches(&excluded, &rel) || by_preset) && !matches(&negated, &rel);
+        let not_kept = !filter.keep.is_empty()
+            && !ALWAYS_KEPT_PATHS.contains(&rel.as_str())
+            && !matches(&kept, &rel);
+        entries.push((rel, entry.file_type().is_dir(), excluded || not_kept));
+    }
+    // Directories with remaining contents must be preserved.
+    let mut nonempty_dirs = HashSet::new();
+    for (rel, _, _) in entries.iter().filter(|e| !e.2) {
+        nonempty_dirs.extend(rel.ancestors().skip(1));
+    }
+
+    let mut removed: Vec<Utf8PathBuf> = Vec::new();
+    let mut total_files = 0;
+    let mut removed_files = 0;
+    for (rel, is_dir, remove) in entries.iter() {
+        if !is_dir {
+            total_files += 1;
+        }
+        // The walk is depth-first, so the contents of a removed directory follow it.
+        if removed.last().is_some_and(|r| rel.starts_with(r)) {
+            if !is_dir {
+                removed_files += 1;
             }
+            continue;
+        }
+        if !remove || nonempty_dirs.contains(rel.as_path()) {
+            continue;
+        }
+        let full_path = path.join(rel);
+        if *is_dir {
+            std::fs::remove_dir_all(&full_path)
+                .with_context(|| format!("Failed to remove directory: {}", full_path))?;
+        } else {
+            std::fs::remove_file(&full_path)
+                .with_context(|| format!("Failed to remove file: {}", full_path))?;
+            removed_files += 1;
         }
+        eprintln!("Removed from crate {name}: {rel}");
+        removed.push(rel.clone());
     }
 
-    if matched {
-        update_checksums_for_removed_paths(path, &removed_patterns)?;
+    if !removed.is_empty() {
+        update_checksums_for_removed_paths(path, &removed)?;
+        eprintln!("Filtered crate {name}: removed {removed_files} of {total_files} files");
     }
-    Ok(())
+    Ok(matched)
 }
 
 /// Update checksums file to remove entries for deleted paths
 fn update_checksums_for_removed_paths(
-    path: &Utf8PathBuf,
+    path: &Utf8Path,
     removed_patterns: &[Utf8PathBuf],
 ) -> Result<()> {
     let checksums_path = path.join(CARGO_CHECKSUM);
@@ -661,15 +1222,6 @@ impl Args {
         }
         all_manifest_paths
     }
-
-    /// Find the root package
-    fn get_root_package(&self) -> Result<Option<Package>> {
-        let mut command = new_metadata_cmd(self.manifest_path.as_deref(), self.offline);
-        command.no_deps();
-
-        let meta = command.exec().context("Executing cargo metadata")?;
-        Ok(meta.root_package().cloned())
-    }
 }
 
 fn new_metadata_cmd(path: Option<&Utf8Path>, offline: bool) -> MetadataCommand {
@@ -683,198 +1235,402 @@ fn new_metadata_cmd(path: Option<&Utf8Path>, offline: bool) -> MetadataCommand {
     command
 }
 
-/// Get filesystem locations of packages vendored by `cargo vendor` (all features enabled)
-fn get_vendored_package_dirs(
-    args: &Args,
-) -> Result<HashMap<cargo_metadata::PackageId, String>> {
-    let root = args.get_root_package()?;
-    let all_manifest_paths = args.get_all_manifest_paths();
-    let mut pkgs_by_name: HashMap<_, Vec<_>> = HashMap::new();
-    for manifest_path in all_manifest_paths {
-        let mut command = new_metadata_cmd(manifest_path, args.offline);
-        command.features(AllFeatures);
-        let meta = command.exec().context("Executing cargo metadata")?;
+/// Retrieve packages needed for selected feature set
+fn get_packages_for_features(
+    resolutions: &[resolve::Resolution],
+) -> Result<HashMap<cargo_metadata::PackageId, cargo_metadata::Package>> {
+    let mut packages = HashMap::new();
+    for resolution in resolutions {
+        let meta = resolution.exec(None)?.metadata;
         meta.packages
             .into_iter()
-            // Skip ourself
-            .filter(|pkg| root.as_ref().is_none_or(|root| pkg.id != root.id))
-            // Also skip anything local
-            .filter(|pkg| {
-                let local = pkg.source.is_none();
-                if local {
-                    eprintln!("Skipping {0}", pkg.name);
-                }
-
-                !local
-            })
-            .for_each(|pkg| {
-                if let Some(pkgs) = pkgs_by_name.get_mut(&pkg.name) {
-                    if !pkgs.contains(&pkg) {
-                        pkgs.push(pkg);
-                    }
-                } else {
-                    pkgs_by_name.insert(pkg.name.clone(), vec![pkg]);
-                }
+            .map(|pkg| (pkg.id.clone(), pkg))
+            .for_each(|(id, pkg)| {
+                packages.insert(id, pkg);
             });
     }
-    let mut pkg_dirs = HashMap::new();
-    for (_name, mut pkgs) in pkgs_by_name {
-        // If we use versioned-dirs, we insert all packages with a versioned filename
-        // If not, we split off the first package and insert it without a version-suffix
-        if !args.versioned_dirs {
-            // Reverse sort - greater version is lower index
-            pkgs.sort_by(|a, b| b.version.cmp(&a.version));
-            let first = pkgs.remove(0);
-            pkg_dirs.insert(first.id, first.name);
-        }
+    Ok(packages)
+}
 
-        for pkg in pkgs {
-            let dir = package_versioned_filename(&pkg);
-            pkg_dirs.insert(pkg.id, dir);
+/// Determine the platforms to filter for, if platform filtering is enabled.
+fn get_platforms(config: &VendorFilter) -> Result<Option<Vec<String>>> {
+    if !config.enables_platform_filtering() {
+        return Ok(None);
+    }
+    eprintln!("Gathering metadata for platforms");
+    let tier_table = tiers::TierTable::load(config.tier_table.as_deref())?;
+    let known_targets = get_target_list(None, &tier_table)?;
+    let mut target_list = match config.tier.as_ref() {
+        Some(tier) => get_target_list(Some(tier), &tier_table)?,
+        None => known_targets.clone(),
+    };
+    if let Some(platform_cfg) = config.platform_cfg.as_deref() {
+        let expr = platform_cfg::parse_cfg_expr(platform_cfg)?;
+        target_list = platform_cfg::filter_targets_by_cfg(&target_list, &expr)?;
+        if target_list.is_empty() {
+            anyhow::bail!("No target platforms match {platform_cfg}");
         }
     }
+    let target_list: Vec<_> = target_list.iter().map(|s| s.as_str()).collect();
+    let known_targets: Vec<_> = known_targets.iter().map(|s| s.as_str()).collect();
+    let exclude = config
+        .exclude_platforms
+        .as_ref()
+        .map(replace_host_keyword)
+        .transpose()?
+        .unwrap_or_default();
+    let exclude: Vec<_> = exclude.iter().map(|s| s.as_str()).collect();
+    // If the user provided an explicit platform list, it may have globs.  Expand it with the known target list.
+    let platforms: Vec<_> = if let Some(platforms) = config.platforms.as_ref() {
+        let platforms = replace_host_keyword(platforms)?;
+        let platforms: Vec<_> = platforms.iter().map(|s| s.as_str()).collect();
+        expand_platforms(&platforms, &exclude, &target_list, &known_targets)?
+    } else {
+        // Here the user didn't provide a platform list; we're just filtering by tier,
+        // cfg expression and/or exclusions.
+        subtract_platforms(target_list, &exclude)?
+    };
+    if platforms.is_empty() {
+        anyhow::bail!("No target platforms left after applying exclude-platforms");
+    }
+    Ok(Some(platforms))
+}
 
-    Ok(pkg_dirs)
+/// The target and host platforms a filter group is filtered to.
+struct GroupPlatforms {
+    /// The target platforms, if filtering by platform.
+    platforms: Option<Vec<String>>,
+    /// The platforms build dependencies and procedural macros are built for.
+    hosts: Vec<String>,
 }
 
-/// Retrieve packages needed for selected feature set
-fn get_packages_for_features(
-    args: &Args,
+/// An additional filter for the edges `(parent, dependency, kind)` of the
+/// dependency graph, on top of the platform and dependency kinds filters.
+type EdgeFilter<'a> = &'a dyn Fn(
+    &cargo_metadata::PackageId,
+    &cargo_metadata::PackageId,
+    &cargo_metadata::DepKindInfo,
+) -> bool;
+
+/// Select the packages required by a filter group: one walk of the dependency
+/// graph for each platform and resolution, along with the entry of `feature-sets`
+/// (by index) the resolution was created for.
+fn select_packages_for_group(
     config: &VendorFilter,
-) -> Result<HashMap<cargo_metadata::PackageId, cargo_metadata::Package>> {
-    let all_manifest_paths = args.get_all_manifest_paths();
-    let mut packages = HashMap::new();
-    for manifest_path in all_manifest_paths {
-        let mut command = new_metadata_cmd(manifest_path, args.offline);
-        if config.all_features {
-            command.features(AllFeatures);
+    resolutions: &[resolve::Resolution],
+    group_platforms: &GroupPlatforms,
+    platform_filter: &mut PlatformFilter,
+    edges: Option<EdgeFilter>,
+) -> Result<Vec<(Option<usize>, resolve::Reached)>> {
+    // Filter each platform and resolution separately; the caller keeps the union of the results
+    let platforms: Vec<Option<&str>> = match group_platforms.platforms.as_deref() {
+        Some(platforms) => platforms.iter().map(|p| Some(p.as_str())).collect(),
+        None => vec![None],
+    };
+    let feature_sets = config.feature_sets.as_deref().unwrap_or_default();
+    let platform_features = config.platform_feature_patterns()?;
+    let mut unmatched_patterns: BTreeSet<_> =
+        platform_features.iter().map(|(p, _)| p.as_str()).collect();
+    let mut walks = Vec::new();
+    for platform in platforms {
+        // Enable the additional features for this platform, if any
+        let mut features = Vec::new();
+        for (pattern, f) in platform_features.iter() {
+            if platform.is_some_and(|p| pattern.matches(p)) {
+                unmatched_patterns.remove(pattern.as_str());
+                features.extend(f.iter().cloned());
+            }
         }
-        if config.no_default_features {
-            command.features(NoDefaultFeatures);
+        for resolution in resolutions.iter() {
+            let resolution = resolution.with_features(&features);
+            let reached = filter_packages(
+                &resolution,
+                config,
+                platform.map(|p| (p, group_platforms.hosts.as_slice())),
+                platform_filter,
+                edges,
+            )?;
+            let feature_set = resolution
+                .feature_set()
+                .and_then(|set| feature_sets.iter().position(|s| s == set));
+            walks.push((feature_set, reached));
         }
-        if !config.features.is_empty() {
-            command.features(SomeFeatures(config.features.clone()));
+    }
+    // Only warn once, when selecting the packages to keep
+    if edges.is_none() {
+        for pattern in unmatched_patterns {
+            eprintln!("Warning: No target platform matches platform-features pattern: {pattern}");
         }
-        let meta = command.exec().context("Executing cargo metadata")?;
-        meta.packages
-            .into_iter()
-            .map(|pkg| (pkg.id.clone(), pkg))
-            .for_each(|(id, pkg)| {
-                packages.insert(id, pkg);
-            });
     }
-    Ok(packages)
+    Ok(walks)
+}
+
+/// Walks resolve graphs for target platforms, loading each platform and running
+/// each `cargo metadata` invocation only once across all platforms and groups.
+struct PlatformFilter {
+    platforms: HashMap<String, Box<dyn platform_cfg::MatchPlatform>>,
+    cache: resolve::ResolvedCache,
 }
 
-/// Using the filter configuration, add references to the `packages` map that
-/// point into the `all_packages` set we already have (to avoid duplicating memory).
-fn add_packages_for_platform<'p>(
-    args: &Args,
+impl PlatformFilter {
+    /// Load the platforms to evaluate the `target` of dependencies against.
+    fn load<'a>(platforms: impl IntoIterator<Item = &'a String>) -> Result<Self> {
+        let mut r: HashMap<_, Box<dyn platform_cfg::MatchPlatform>> = HashMap::new();
+        let mut targets = HashSet::new();
+        for platform in platforms {
+            if target_spec::is_target_spec(platform) {
+                // Custom targets are not known to rustc, so we use the specification
+                let spec = target_spec::TargetSpec::load(Utf8Path::new(platform))?;
+                r.insert(platform.clone(), Box::new(spec));
+            } else {
+                targets.insert(platform);
+            }
+        }
+        for target in platform_cfg::TargetCfg::for_targets(targets)? {
+            r.insert(target.name().to_string(), Box::new(target));
+        }
+        Ok(Self {
+            platforms: r,
+            cache: Default::default(),
+        })
+    }
+
+    /// Resolve for a platform with `cargo metadata --filter-platform`, or without
+    /// filtering for custom targets, which cargo can't filter for.
+    fn exec(
+        &mut self,
+        resolution: &resolve::Resolution,
+        platform: &str,
+    ) -> Result<Rc<resolve::Resolved>> {
+        let filter = (!target_spec::is_target_spec(platform)).then_some(platform);
+        self.cache.exec(resolution, filter)
+    }
+
+    /// Return the packages a resolution needs on `target`, with build dependencies
+    /// and proc-macros followed in the resolve graph of each of the `hosts`.
+    fn walk(
+        &mut self,
+        resolution: &resolve::Resolution,
+        target: &str,
+        hosts: &[String],
+        follow: impl Fn(
+            &cargo_metadata::PackageId,
+            &cargo_metadata::NodeDep,
+            &cargo_metadata::DepKindInfo,
+        ) -> bool,
+    ) -> Result<resolve::Reached> {
+        let resolved = self.exec(resolution, target)?;
+        let host_resolved = hosts
+            .iter()
+            .map(|h| self.exec(resolution, h))
+            .collect::<Result<Vec<_>>>()?;
+        let platform = |p: &str| {
+            self.platforms
+                .get(p)
+                .map(|m| m.as_ref())
+                .ok_or_else(|| anyhow!("Platform {p} was not loaded"))
+        };
+        let host_graphs = host_resolved
+            .iter()
+            .zip(hosts)
+            .map(|(r, h)| Ok((r.as_ref(), platform(h)?)))
+            .collect::<Result<Vec<_>>>()?;
+        resolved.walk_for_target(platform(target)?, &host_graphs, follow)
+    }
+}
+
+/// Select the packages required for a single resolution on a platform and its
+/// hosts (or all platforms), taking the dependency kinds filter into account.
+fn filter_packages(
+    resolution: &resolve::Resolution,
+    config: &VendorFilter,
+    platform: Option<(&str, &[String])>,
+    platform_filter: &mut PlatformFilter,
+    edges: Option<EdgeFilter>,
+) -> Result<resolve::Reached> {
+    let kinds = |kind| {
+        config
+            .keep_dep_kinds
+            .is_none_or(|k| k == dep_kinds_filtering::DepKinds::All || k.includes(kind))
+    };
+    let follow = |parent: &_, dep: &cargo_metadata::NodeDep, kind: &cargo_metadata::DepKindInfo| {
+        kinds(kind.kind) && edges.is_none_or(|f| f(parent, &dep.pkg, kind))
+    };
+    match platform {
+        Some((platform, hosts)) => platform_filter.walk(resolution, platform, hosts, follow),
+        None => {
+            let resolved = platform_filter.cache.exec(resolution, None)?;
+            resolved.walk(|parent, dep| dep.dep_kinds.iter().any(|k| follow(parent, dep, k)))
+        }
+    }
+}
+
+/// Add the packages selected by `keep-crates` (and optionally everything they
+/// depend on) back into the filtered set, regardless of platform and dependency
+/// kind filtering.  Returns the names of the crates that were added back.
+fn add_kept_packages<'p>(
+    resolutions: &[resolve::Resolution],
     config: &VendorFilter,
     all_packages: &'p HashMap<cargo_metadata::PackageId, cargo_metadata::Package>,
     packages: &mut HashMap<cargo_metadata::PackageId, &'p cargo_metadata::Package>,
-    platform: Option<&str>,
-) -> Result<()> {
-    let all_manifest_paths = args.get_all_manifest_paths();
-    for manifest_path in all_manifest_paths {
-        let mut command = new_metadata_cmd(manifest_path, args.offline);
-        if config.all_features {
-            command.features(AllFeatures);
-        }
-        if config.no_default_features {
-            command.features(NoDefaultFeatures);
-        }
-        if !config.features.is_empty() {
-            command.features(SomeFeatures(config.features.clone()));
+) -> Result<BTreeSet<String>> {
+    let patterns = config.keep_crate_patterns()?;
+    let mut kept: HashSet<_> = all_packages
+        .values()
+        .filter(|pkg| patterns.iter().any(|p| p.matches(&pkg.name)))
+        .map(|pkg| pkg.id.clone())
+        .collect();
+    for pattern in patterns.iter() {
+        if !kept
+            .iter()
+            .any(|id| pattern.matches(&all_packages[id].name))
+        {
+            eprintln!("Warning: No match for kept crate: {pattern}");
         }
-        if let Some(platform) = platform {
-            command.other_options(vec![format!("--filter-platform={platform}")]);
+    }
+
+    if config.keep_crates_dependencies && !kept.is_empty() {
+        let mut closure = HashSet::new();
+        for resolution in resolutions {
+            let resolved = resolution.exec(None)?;
+            // Not all kept crates are part of every resolution
+            let resolve = resolved.metadata.resolve.iter();
+            let nodes: HashSet<_> = resolve
+                .flat_map(|r| r.nodes.iter().map(|n| &n.id))
+                .collect();
+            let start = kept.iter().filter(|id| nodes.contains(id));
+            closure.extend(resolved.walk_from(start, |_, _| true)?.into_packages());
         }
+        kept.extend(closure);
+    }
 
-        let meta = command.exec().context("Executing cargo metadata")?;
-        for package in meta.packages {
-            let package = all_packages
-                .get(&package.id)
-                .ok_or_else(|| anyhow!("Failed to find package {}", package.name))
-                .unwrap();
-            packages.insert(package.id.clone(), package);
+    let mut added = BTreeSet::new();
+    for id in kept {
+        let Some(package) = all_packages.get(&id) else {
+            continue;
+        };
+        if packages.insert(id, package).is_none() && package.source.is_some() {
+            added.insert(format!("{} {}", package.name, package.version));
         }
     }
-    Ok(())
+    Ok(added)
 }
 
-/// Parse the output of `rustc --print target-list`
-fn get_target_list(tier: Option<&tiers::Tier>) -> Result<HashSet<String>> {
+/// Parse the output of `rustc --print target-list`, optionally restricted to a tier.
+/// Targets of the tier table which are unknown to the running rustc are skipped.
+fn get_target_list(
+    tier: Option<&tiers::TierSelector>,
+    tier_table: &tiers::TierTable,
+) -> Result<HashSet<String>> {
+    let o = Command::new("rustc")
+        .args(["--print", "target-list"])
+        .output()
+        .context("Failed to invoke rustc --print target-list")?;
+    let buf = String::from_utf8(o.stdout)?;
+    let targets: HashSet<_> = buf.lines().map(|s| s.trim().to_string()).collect();
     if let Some(tier) = tier {
-        Ok(tier.targets().map(|v| v.to_string()).collect())
+        Ok(tier_table
+            .targets(tier)
+            .filter(|&v| targets.contains(v))
+            .map(|v| v.to_string())
+            .collect())
     } else {
-        let o = Command::new("rustc")
-            .args(["--print", "target-list"])
-            .output()
-            .context("Failed to invoke rustc --print target-list")?;
-        let buf = String::from_utf8(o.stdout)?;
-        Ok(buf.lines().map(|s| s.trim().to_string()).collect())
+        Ok(targets)
     }
 }
 
-type ParsedPlatform<'a> = SmallVec<[&'a str; 4]>;
+/// Returns true if the platform is a glob pattern rather than a literal target.
+fn is_platform_pattern(platform: &str) -> bool {
+    glob::Pattern::escape(platform) != platform
+}
 
-fn platform_matches(platform: &ParsedPlatform, o: &ParsedPlatform) -> bool {
-    if platform.len() != o.len() {
-        return false;
+/// Expand a glob pattern matched against the whole target triple.
+fn expand_one_platform<'t>(platform: &str, target_list: &[&'t str]) -> Result<Vec<&'t str>> {
+    let pattern = glob::Pattern::new(platform)
+        .with_context(|| format!("Invalid platform pattern: {platform}"))?;
+    let r: Vec<_> = target_list
+        .iter()
+        .copied()
+        .filter(|target| pattern.matches(target))
+        .collect();
+    if r.is_empty() {
+        anyhow::bail!("No known target platform matches {platform}");
     }
-    for (t, p) in platform.iter().zip(o.iter()) {
-        if *p == "*" {
-            continue;
-        }
-        if p != t {
-            return false;
+    Ok(r)
+}
+
+/// Expand the glob patterns in `platforms` using `target_list`, and remove
+/// those matching `exclude`.  Literal targets are validated against
+/// `known_targets`, except for custom target specifications.
+fn expand_platforms<'b>(
+    platforms: &'b [&'b str],
+    exclude: &[&str],
+    target_list: &[&'b str],
+    known_targets: &[&str],
+) -> Result<Vec<String>> {
+    let mut r = Vec::new();
+    for &platform in platforms {
+        if target_spec::is_target_spec(platform) {
+            r.push(platform);
+        } else if is_platform_pattern(platform) {
+            r.extend(expand_one_platform(platform, target_list)?);
+        } else if known_targets.contains(&platform) {
+            r.push(platform);
+        } else {
+            anyhow::bail!("Unknown target platform: {platform}");
         }
     }
-    true
+    subtract_platforms(r, exclude)
 }
 
-fn expand_one_platform<'t>(
-    platform: &str,
-    target_list: &'t [(&str, ParsedPlatform)],
-) -> Vec<&'t str> {
-    let platform_parts: ParsedPlatform = platform.split('-').collect();
-    let mut r = Vec::new();
-    for (target, target_parts) in target_list {
-        if platform_matches(target_parts, &platform_parts) {
-            r.push(*target)
+/// Remove the platforms matching any of the `exclude` glob patterns, and
+/// return the remaining ones sorted and deduplicated.
+fn subtract_platforms(platforms: Vec<&str>, exclude: &[&str]) -> Result<Vec<String>> {
+    let exclude = exclude
+        .iter()
+        .map(|&p| glob::Pattern::new(p).with_context(|| format!("Invalid platform pattern: {p}")))
+        .collect::<Result<Vec<_>>>()?;
+    for e in exclude.iter() {
+        if !platforms.iter().any(|platform| e.matches(platform)) {
+            eprintln!("Warning: exclude-platforms pattern {e} matches no platforms");
         }
     }
-    r
+    let r: BTreeSet<_> = platforms
+        .into_iter()
+        .filter(|platform| !exclude.iter().any(|e| e.matches(platform)))
+        .collect();
+    Ok(r.into_iter().map(ToOwned::to_owned).collect())
 }
 
-fn expand_platforms<'b>(
-    platforms: &'b [&'b str],
-    target_list: &[(&str, ParsedPlatform)],
-) -> Result<Vec<String>> {
-    let r = platforms
+/// The platform keyword for the host of the running rustc.
+const HOST_PLATFORM: &str = "host";
+
+/// Replace the `host` keyword in a list of platforms with the host of the running rustc.
+fn replace_host_keyword(platforms: &BTreeSet<String>) -> Result<Vec<String>> {
+    platforms
         .iter()
-        .flat_map(|&platform| {
-            if platform.contains('*') {
-                Either::Left(expand_one_platform(platform, target_list).into_iter())
+        .map(|p| {
+            if p == HOST_PLATFORM {
+                platform_cfg::rustc_host()
             } else {
-                Either::Right([platform].into_iter())
+                Ok(p.clone())
             }
         })
-        .map(ToOwned::to_owned) // Clone to avoid need for common lifetimes
-        .collect();
-    Ok(r)
+        .collect()
 }
 
 /// Deletes unreferenced packages from the vendor directory.
-fn delete_unreferenced_packages(
+fn delete_unreferenced_packages<'a>(
     output_dir: &Utf8Path,
     package_filenames: &BTreeMap<Cow<'_, str>, &Package>,
-    excludes: &HashMap<&str, HashSet<&str>>,
-) -> Result<()> {
+    crate_paths: &HashMap<&str, CratePaths<'a>>,
+    stub_style: StubStyle,
+    stub_reason: impl Fn(&str) -> String,
+) -> Result<HashMap<String, CratePaths<'a>>> {
     // A reusable buffer (silly optimization to avoid allocating lots of path buffers)
     let mut pbuf = Utf8PathBuf::from(&output_dir);
     let mut unreferenced = HashSet::new();
+    let mut matched_paths = HashMap::new();
 
     // Deleting files while iterating a `read_dir` produces undefined behaviour.
     let mut entries = Vec::new();
@@ -888,32 +1644,92 @@ fn delete_unreferenced_packages(
         pbuf.push(name);
 
         if !package_filenames.contains_key(&Cow::Borrowed(name)) {
-            replace_with_stub(&pbuf).with_context(|| format!("Replacing with stub: {name}"))?;
+            replace_with_stub(&pbuf, stub_style, &stub_reason(name))
+                .with_context(|| format!("Replacing with stub: {name}"))?;
             eprintln!("Replacing unreferenced package with stub: {name}");
             assert!(unreferenced.insert(name.to_string()));
-        }
-
-        if let Some(crate_excludes) = excludes.get(name) {
-            process_excludes(&pbuf, name, crate_excludes)?;
-        }
-        if let Some(generic_excludes) = excludes.get("*") {
-            process_excludes(&pbuf, name, generic_excludes)?;
+        } else if let Some(filter) = crate_paths.get(name) {
+            let matched = process_crate_paths(&pbuf, name, filter)?;
+            matched_paths.insert(name.to_string(), matched);
         }
 
         let r = pbuf.pop();
         debug_assert!(r);
     }
 
+    Ok(matched_paths)
+}
+
+/// Report the `exclude-crate-paths` and `keep-crate-paths` patterns which matched
+/// nothing in any vendored crate.  With `strict-excludes`, fail if any of them
+/// names a specific crate.
+fn report_unmatched_crate_paths(
+    config: &VendorFilter,
+    vendored_dirs: &BTreeMap<String, vendored::VendoredPackage>,
+    package_filenames: &BTreeMap<Cow<'_, str>, &Package>,
+    matched_paths: &HashMap<String, CratePaths<'_>>,
+) -> Result<()> {
+    let excludes = config.exclude_crate_paths.iter().flatten();
+    let excludes = excludes.map(|e| ("exclude-crate-paths", &e.name, e.exclude.as_str(), true));
+    let keep = config.keep_crate_paths.iter().flatten();
+    let keep = keep.map(|k| ("keep-crate-paths", &k.name, k.keep.as_str(), false));
+    let mut unmatched = BTreeSet::new();
+    for (key, selector, pattern, is_exclude) in excludes.chain(keep) {
+        let dirs: Vec<&str> = vendored_dirs
+            .iter()
+            .filter(|(_, v)| selector.matches(&v.name, &v.version))
+            .map(|(dir, _)| dir.as_str())
+            .collect();
+        // Paths are not filtered in crates replaced with stubs.
+        let is_stub = |dir: &&str| !package_filenames.contains_key(&Cow::Borrowed(*dir));
+        if !dirs.is_empty() && dirs.iter().all(is_stub) {
+            continue;
+        }
+        let found = dirs.iter().any(|&dir| {
+            matched_paths.get(dir).is_some_and(|m| {
+                let patterns = if is_exclude { &m.excludes } else { &m.keep };
+                patterns.contains(pattern)
+            })
+        });
+        if !found {
+            unmatched.insert((key, format!("{selector}#{pattern}"), selector.is_specific()));
+        }
+    }
+    if unmatched.is_empty() {
+        return Ok(());
+    }
+    eprintln!("Warning: Crate path patterns matching nothing:");
+    for (key, pattern, _) in unmatched.iter() {
+        eprintln!("  {key}: {pattern}");
+    }
+    if config.strict_excludes {
+        let specific: Vec<&str> = unmatched
+            .iter()
+            .filter(|(_, _, specific)| *specific)
+            .map(|(_, pattern, _)| pattern.as_str())
+            .collect();
+        if !specific.is_empty() {
+            anyhow::bail!(
+                "Crate-specific path patterns matched nothing (strict-excludes): {}",
+                specific.join(", ")
+            );
+        }
+    }
     Ok(())
 }
 
-/// Return the filename cargo vendor would use for a package which has multiple versions present
-fn package_versioned_filename(p: &Package) -> String {
-    format!("{}-{}", p.name, p.version)
+/// Print the tier table selected by the configuration.
+fn print_tier_table(args: &Args) -> Result<()> {
+    let config = gather_config(args)?.unwrap_or_default();
+    let table = tiers::TierTable::load(config.tier_table.as_deref())?;
+    table.print(std::io::stdout().lock())
 }
 
 /// An inner version of `main`; the primary code.
 pub fn run(args: Args) -> Result<()> {
+    if let Some(Subcommand::PrintTierTable) = args.command {
+        return print_tier_table(&args);
+    }
 
     let (had_config, config) = if let Some(c) = gather_config(&args)? {
         (true, c)
@@ -966,48 +1782,156 @@ pub fn run(args: Args) -> Result<()> {
         anyhow::bail!("Refusing to operate on extant directory: {}", output_dir);
     }
 
-    // We need to gather the full, unfiltered metadata to canonically know what
-    // `cargo vendor` will do.
-    eprintln!("Gathering metadata for vendored packages");
-    let vendored_dirs = get_vendored_package_dirs(&args)?;
     eprintln!("Gathering metadata for selected feature set");
-    let all_packages = get_packages_for_features(&args, &config)?;
+    let groups = config.groups()?;
+    let group_resolutions = groups
+        .iter()
+        .map(|group| resolve::get_resolutions(&args, group))
+        .collect::<Result<Vec<_>>>()?;
+    // Features are additive, so enabling all platform specific features gives
+    // us the superset of packages needed for any platform.
+    let platform_features = config.all_platform_features();
+    let resolutions: Vec<_> = group_resolutions
+        .iter()
+        .flatten()
+        .map(|r| r.with_features(&platform_features))
+        .collect();
+    let all_packages = get_packages_for_features(&resolutions)?;
 
-    // And now do the filtered set
+    // And now do the filtered set, keeping the union of all groups
     let mut packages = HashMap::new();
-    let mut expanded_platforms = None;
-    if config.enables_platform_filtering() {
-        eprintln!("Gathering metadata for platforms");
-        let target_list = get_target_list(config.tier.as_ref())?;
-        let target_list: Vec<(&str, ParsedPlatform)> = target_list
+    let mut feature_sets_by_package = HashMap::new();
+    let mut walks = Vec::new();
+    let mut expanded_platforms = Vec::new();
+    for group in groups.iter() {
+        let platforms = get_platforms(group)?;
+        // Host platforms only matter when filtering by platform
+        let hosts = match platforms {
+            Some(_) => group.host_platforms()?,
+            None => Vec::new(),
+        };
+        expanded_platforms.push(GroupPlatforms { platforms, hosts });
+    }
+    // Groups share platforms and resolutions, so load each of them only once.
+    let mut platform_filter = PlatformFilter::load(
+        expanded_platforms
             .iter()
-            .map(|platform| (platform.as_str(), platform.split('-').collect()))
+            .flat_map(|g| g.platforms.iter().flatten().chain(&g.hosts)),
+    )?;
+    for ((group, resolutions), group_platforms) in groups
+        .iter()
+        .zip(group_resolutions.iter())
+        .zip(expanded_platforms.iter())
+    {
+        let selected = select_packages_for_group(
+            group,
+            resolutions,
+            group_platforms,
+            &mut platform_filter,
+            None,
+        )?;
+        for (feature_set, reached) in selected.iter() {
+            for id in reached.packages() {
+                let package = all_packages
+                    .get(id)
+                    .ok_or_else(|| anyhow!("Failed to find package {id}"))?;
+                if let Some(i) = feature_set {
+                    feature_sets_by_package
+                        .entry(id.clone())
+                        .or_insert_with(BTreeSet::new)
+                        .insert(*i);
+                }
+                packages.insert(id.clone(), package);
+            }
+        }
+        walks.extend(selected.into_iter().map(|(_, reached)| reached));
+    }
+
+    // Explicitly kept crates override the platform and dependency kinds filters.
+    let kept_crates = add_kept_packages(&resolutions, &config, &all_packages, &mut packages)?;
+
+    // Explicitly excluded crates are replaced with stubs even if they are required.
+    let exclude_crates = config.exclude_crate_patterns()?;
+    let unmatched_exclude_crates: Vec<_> = exclude_crates
+        .iter()
+        .filter(|p| !all_packages.values().any(|pkg| p.matches(&pkg.name)))
+        .map(|p| p.as_str())
+        .collect();
+    for pattern in unmatched_exclude_crates.iter() {
+        eprintln!("Warning: No match for excluded crate: {pattern}");
+    }
+    if config.strict_excludes && !unmatched_exclude_crates.is_empty() {
+        anyhow::bail!(
+            "exclude-crates patterns matched nothing (strict-excludes): {}",
+            unmatched_exclude_crates.join(", ")
+        );
+    }
+    let mut excluded_crates = BTreeSet::new();
+    packages.retain(|_, pkg| {
+        let excluded = exclude_crates.iter().any(|p| p.matches(&pkg.name));
+        if excluded && pkg.source.is_some() {
+            excluded_crates.insert(format!("{} {}", pkg.name, pkg.version));
+        }
+        !excluded
+    });
+
+    // The dependency path which selected a package, for diagnostics.
+    let dependency_path = |id: &cargo_metadata::PackageId| {
+        let path = walks.iter().find_map(|reached| reached.path(id))?;
+        let path: Vec<_> = path
+            .into_iter()
+            .map(|id| match all_packages.get(id) {
+                Some(pkg) => format!("{} {}", pkg.name, pkg.version),
+                None => id.to_string(),
+            })
             .collect();
-        // If the user provided an explicit platform list, it may have globs.  Expand it with the known target list.
-        let platforms: Vec<_> = if let Some(platforms) = config.platforms.as_ref() {
-            let platforms: Vec<_> = platforms.iter().map(|s| s.as_str()).collect();
-            expand_platforms(&platforms, &target_list)?
-        } else {
-            // Here the user didn't provide a platform list; we're just filtering by tier.
-            assert!(config.tier.is_some());
-            let mut v: Vec<_> = target_list.into_iter().map(|v| v.0.to_string()).collect();
-            v.sort();
-            v
-        };
-        for platform in platforms.iter() {
-            add_packages_for_platform(
-                &args,
-                &config,
-                &all_packages,
-                &mut packages,
-                Some(platform),
-            )?;
-            dep_kinds_filtering::filter_dep_kinds(&args, &config, &mut packages, Some(platform))?;
+        Some(path.join(" -> "))
+    };
+
+    // Enforce the license policy on everything we are going to keep.
+    let license_stubbed_crates =
+        licenses::check_licenses(&config, &mut packages, dependency_path, || {
+            // Select the packages again, only following non-optional dependencies.
+            let non_optional = |parent: &_, dep: &_, kind: &_| match (
+                all_packages.get(parent),
+                all_packages.get(dep),
+            ) {
+                (Some(parent), Some(dep)) => !licenses::is_optional_dependency(parent, dep, kind),
+                _ => true,
+            };
+            let mut required = HashSet::new();
+            for ((group, resolutions), group_platforms) in groups
+                .iter()
+                .zip(group_resolutions.iter())
+                .zip(expanded_platforms.iter())
+            {
+                let selected = select_packages_for_group(
+                    group,
+                    resolutions,
+                    group_platforms,
+                    &mut platform_filter,
+                    Some(&non_optional),
+                )?;
+                let selected = selected.into_iter().flat_map(|(_, r)| r.into_packages());
+                required.extend(selected);
+            }
+            Ok(required)
+        })?;
+
+    // Record which feature sets required the crates we are going to keep.
+    let feature_sets = config.feature_sets.as_deref().unwrap_or_default();
+    let mut feature_set_crates = BTreeMap::new();
+    for (id, indices) in feature_sets_by_package {
+        match packages.get(&id) {
+            Some(pkg) if pkg.source.is_some() => {
+                let required_by: Vec<_> = indices
+                    .into_iter()
+                    .map(|i| format!("{:?}", feature_sets[i]))
+                    .collect();
+                feature_set_crates.insert(format!("{} {}", pkg.name, pkg.version), required_by);
+            }
+            _ => {}
         }
-        expanded_platforms = Some(platforms);
-    } else {
-        add_packages_for_platform(&args, &config, &all_packages, &mut packages, None)?;
-        dep_kinds_filtering::filter_dep_kinds(&args, &config, &mut packages, None)?;
     }
 
     // Run `cargo vendor` which will capture all dependencies.
@@ -1023,7 +1947,7 @@ pub fn run(args: Args) -> Result<()> {
         .args(args.respect_source_config.then_some(RESPECT_SOURCE_CONFIG))
         .args(args.versioned_dirs.then_some(VERSIONED_DIRS))
         .args(manifest_path.iter().flatten());
-    if let Some(sync) = args.sync {
+    if let Some(sync) = args.sync.as_ref() {
         for s in sync {
             builder.args([SYNC, s.as_str()]);
         }
@@ -1033,26 +1957,102 @@ pub fn run(args: Args) -> Result<()> {
         anyhow::bail!("Failed to execute cargo vendor: {:?}", status);
     }
 
-    // Determine the set of vendored components we want to keep, by intersecting
-    // the all_packages map with the filtered one, returning an index by the
-    // directory name that will have been generated by `cargo vendor`.
+    // Identify the directories generated by `cargo vendor` from their manifests,
+    // and index the packages we want to keep by their directory name.
+    let lockfiles = vendored::load_lockfiles(&args)?;
+    vendored::check_conflicting_checksums(&lockfiles)?;
+    let vendored_dirs = vendored::identify_vendored_dirs(&output_dir, &lockfiles)?;
+    let dirs_by_package: HashMap<_, _> = vendored_dirs
+        .iter()
+        .map(|(dir, v)| ((v.name.as_str(), v.version.as_str()), dir.as_str()))
+        .collect();
     let mut package_filenames = BTreeMap::new();
-    for (id, pkg) in packages {
-        if let Some(dir) = vendored_dirs.get(&id) {
-            package_filenames.insert(Cow::Borrowed(dir.as_str()), pkg);
-        }
+    // Local packages are not vendored
+    for pkg in packages.into_values().filter(|pkg| pkg.source.is_some()) {
+        let version = pkg.version.to_string();
+        let dir = dirs_by_package
+            .get(&(pkg.name.as_str(), version.as_str()))
+            .ok_or_else(|| anyhow!("Package {} {version} was not vendored", pkg.name))?;
+        package_filenames.insert(Cow::Borrowed(*dir), pkg);
     }
+    // Verify that everything in the lockfiles is accounted for.
+    let (kept_count, stubbed_count) =
+        vendored::check_vendored(&lockfiles, &vendored_dirs, |dir| {
+            package_filenames.contains_key(dir)
+        })?;
 
-    // Index the excludes into a mapping from crate name -> [list of excludes].
-    let mut excludes: HashMap<&str, HashSet<&str>> = HashMap::new();
-    if let Some(exclude_paths) = &config.exclude_crate_paths {
-        for ex_path in exclude_paths {
-            let e = excludes.entry(ex_path.name.as_str()).or_default();
-            e.insert(ex_path.exclude.as_str());
+    // Index the excludes and keep paths by directory, matching their crate selectors
+    // against the package each directory holds (a crate may be vendored in several
+    // versions).
+    let mut crate_paths: HashMap<&str, CratePaths> = HashMap::new();
+    for (dir, vendored) in vendored_dirs.iter() {
+        for ex_path in config.exclude_crate_paths.iter().flatten() {
+            if ex_path.name.matches(&vendored.name, &vendored.version) {
+                let e = crate_paths.entry(dir.as_str()).or_default();
+                e.excludes.insert(ex_path.exclude.as_str());
+            }
+        }
+        for keep_path in config.keep_crate_paths.iter().flatten() {
+            if keep_path.name.matches(&vendored.name, &vendored.version) {
+                let e = crate_paths.entry(dir.as_str()).or_default();
+                e.keep.insert(keep_path.keep.as_str());
+            }
+        }
+        if let Some(presets) = config.exclude_presets.as_ref().filter(|p| !p.is_empty()) {
+            let e = crate_paths.entry(dir.as_str()).or_default();
+            e.presets.clone_from(presets);
         }
     }
 
-    delete_unreferenced_packages(&output_dir, &package_filenames, &excludes)?;
+    // Collect the packages vendored from each git source for the summary.
+    let mut git_sources: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (dir, vendored) in vendored_dirs.iter().filter(|(_, v)| v.is_git) {
+        let stub = if package_filenames.contains_key(dir.as_str()) {
+            ""
+        } else {
+            " (stub)"
+        };
+        let entry = git_sources.entry(vendored.source.as_str()).or_default();
+        entry.push(format!("{} {}{stub}", vendored.name, vendored.version));
+    }
+
+    // Describe why crates were replaced with stubs, for `stub-style = "compile-error"`.
+    let platforms: BTreeSet<_> = expanded_platforms
+        .iter()
+        .filter_map(|g| g.platforms.as_ref())
+        .flatten()
+        .collect();
+    let default_reason = if platforms.is_empty() {
+        "not needed for the selected features and dependency kinds".to_string()
+    } else {
+        format!("not needed for platforms {:?}", Vec::from_iter(platforms))
+    };
+    let stub_reason = |dir: &str| {
+        let Some(vendored) = vendored_dirs.get(dir) else {
+            return default_reason.clone();
+        };
+        let name = format!("{} {}", vendored.name, vendored.version);
+        if excluded_crates.contains(&name) {
+            "excluded by exclude-crates".to_string()
+        } else if license_stubbed_crates.contains(&name) {
+            "rejected by the license policy".to_string()
+        } else {
+            default_reason.clone()
+        }
+    };
+    let stub_style = config.stub_style.unwrap_or_default();
+    let matched_paths = delete_unreferenced_packages(
+        &output_dir,
+        &package_filenames,
+        &crate_paths,
+        stub_style,
+        stub_reason,
+    )?;
+    report_unmatched_crate_paths(&config, &vendored_dirs, &package_filenames, &matched_paths)?;
+    if let Some(mode) = config.strip_binaries {
+        let kept_dirs = package_filenames.keys().map(|dir| dir.as_ref());
+        binaries::strip_binaries(&output_dir, kept_dirs, mode)?;
+    }
 
     // For tar archives, generate them now from the temporary directory.
     let prefix = args.prefix.as_deref();
@@ -1069,18 +2069,56 @@ pub fn run(args: Args) -> Result<()> {
 
     if !had_config {
         eprintln!("Notice: No vendor filtering enabled");
-    } else if let Some(platforms) = expanded_platforms {
-        eprintln!("Filtered to target platforms: {:?}", platforms);
     }
-    if let Some(keep_dep_kinds) = config.keep_dep_kinds {
-        eprintln!("Filtered to dependency kinds: {keep_dep_kinds}");
+    for (i, (group, GroupPlatforms { platforms, hosts })) in
+        groups.iter().zip(expanded_platforms).enumerate()
+    {
+        let prefix = if config.groups.is_some() {
+            format!("groups[{i}]: ")
+        } else {
+            String::new()
+        };
+        if let Some(platforms) = platforms {
+            eprintln!("{prefix}Filtered to target platforms: {:?}", platforms);
+            eprintln!("{prefix}Filtered host dependencies to platforms: {hosts:?}");
+        }
+        if let Some(keep_dep_kinds) = group.keep_dep_kinds {
+            eprintln!("{prefix}Filtered to dependency kinds: {keep_dep_kinds}");
+        }
+    }
+    if !kept_crates.is_empty() {
+        eprintln!("Kept crates: {:?}", kept_crates);
+    }
+    if !excluded_crates.is_empty() {
+        eprintln!("Excluded crates: {:?}", excluded_crates);
+    }
+    if !license_stubbed_crates.is_empty() {
+        eprintln!(
+            "Excluded crates by license policy: {:?}",
+            license_stubbed_crates
+        );
+    }
+
+    eprintln!("Vendored packages: {kept_count} kept, {stubbed_count} replaced with stubs");
+    if !git_sources.is_empty() {
+        eprintln!("Git sources:");
+        for (source, mut crates) in git_sources {
+            crates.sort();
+            eprintln!("  {source}: {}", crates.join(", "));
+        }
+    }
+
+    if !feature_set_crates.is_empty() {
+        eprintln!("Feature sets requiring each crate:");
+        for (name, required_by) in feature_set_crates {
+            eprintln!("  {name}: {}", required_by.join(", "));
+        }
     }
 
     eprintln!("Generated: {final_output_path}");
     Ok(())
 }
 
-
 #[test]
 fn test_parse_config() {
     use serde_json::json;
@@ -1094,13 +2132,86 @@ fn test_parse_config() {
         json!({ "platforms": ["aarch64-unknown-linux-gnu"], "all-features": true, "no-default-features": false, "keep-dep-kinds": "dev"}),
         json!({ "platforms": ["aarch64-unknown-linux-gnu"], "no-default-features": true, "keep-dep-kinds": "no-build"}),
         json!({ "platforms": ["aarch64-unknown-linux-gnu"], "no-default-features": true, "features": ["first-feature", "second-feature"], "keep-dep-kinds": "no-build"}),
+        json!({ "platform-cfg": "cfg(all(unix, not(target_os = \"macos\")))", "tier": "2"}),
+        json!({ "platforms": ["*-unknown-linux-gnu"], "tier": "3", "tier-table": "tiers.toml"}),
+        json!({ "tier": "2-with-host-tools"}),
+        json!({ "platforms": ["*-unknown-linux-*", "host"], "exclude-platforms": ["*-linux-musl*", "sparc*"]}),
+        json!({ "platforms": ["aarch64-unknown-linux-gnu"], "host-platforms": ["x86_64-unknown-linux-gnu"]}),
+        json!({ "features": ["common"], "feature-sets": [["rustls"], ["openssl", "vendored"]]}),
+        json!({ "stub-style": "compile-error"}),
     ];
     for case in valid {
         let _: VendorFilter = serde_json::from_value(case).unwrap();
     }
+    let filter = json!({ "feature-sets": [] });
+    let e = serde_json::from_value::<VendorFilter>(filter).unwrap_err();
+    assert!(e.to_string().contains("At least one feature set"), "{e}");
     let filter = json!({ "exclude-crate-paths": [ { "name": "hex", "exclude": "benches" }, { "name": "curl", "exclude": "curl" } ]});
     let r: VendorFilter = serde_json::from_value(filter).unwrap();
     assert_eq!(r.exclude_crate_paths.unwrap().len(), 2);
+    let filter = json!({ "keep-crate-paths": [ { "name": "libz-sys", "keep": "src/*.rs" }, { "name": "libz-sys", "keep": "build.rs" } ]});
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    assert_eq!(r.keep_crate_paths.unwrap().len(), 2);
+    let filter = json!({ "exclude-crate-paths": [ { "name": "libz-sys", "exclude": "src/zlib" } ], "strict-excludes": true });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    assert!(r.strict_excludes);
+    let filter =
+        json!({ "exclude-presets": ["tests", "benches", "examples", "docs", "ci", "fuzz"] });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    assert_eq!(r.exclude_presets.unwrap().len(), 6);
+    let filter = json!({ "exclude-presets": ["vendor"] });
+    assert!(serde_json::from_value::<VendorFilter>(filter).is_err());
+    let filter = json!({ "strip-binaries": "remove" });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    assert_eq!(r.strip_binaries, Some(binaries::StripBinaries::Remove));
+    let filter = json!({ "keep-crates": [ "windows-sys" ], "keep-crates-dependencies": true });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    assert_eq!(r.keep_crate_patterns().unwrap().len(), 1);
+    assert!(r.keep_crates_dependencies);
+    let filter = json!({ "allowed-licenses": [ "MIT", "Apache-2.0" ], "denied-licenses": [ "GPL-3.0-only" ], "license-policy": "stub" });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    assert_eq!(r.allowed_licenses.unwrap().len(), 2);
+    assert_eq!(r.license_policy, Some(licenses::LicensePolicy::Stub));
+    let filter = json!({ "features": ["a"], "members": { "foo": { "all-features": true }, "bar": { "no-default-features": true, "features": ["b"] } } });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    assert_eq!(r.feature_selection().features, ["a"]);
+    assert!(r.members["foo"].all_features);
+    assert_eq!(r.members["bar"].features, ["b"]);
+    let filter = json!({ "packages": ["foo-*"], "exclude-packages": ["foo-test"] });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    assert!(r.package_patterns().unwrap()[0].matches("foo-cli"));
+    assert_eq!(r.exclude_package_patterns().unwrap().len(), 1);
+    let filter = json!({ "platforms": ["*-linux-gnu", "x86_64-pc-windows-msvc"], "platform-features": { "*-linux-gnu": ["systemd"], "*-windows-*": ["wmi", "systemd"] } });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    let patterns = r.platform_feature_patterns().unwrap();
+    assert!(patterns[0].0.matches("x86_64-unknown-linux-gnu"));
+    assert_eq!(patterns[0].1, ["systemd"]);
+    assert_eq!(r.all_platform_features(), ["systemd", "wmi"]);
+    let filter = json!({ "groups": [ { "platforms": ["*-unknown-linux-gnu"], "all-features": true, "keep-dep-kinds": "normal" }, { "tier": "1", "features": ["a"] } ], "keep-crates": ["foo"] });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    let groups = r.groups().unwrap();
+    assert_eq!(groups.len(), 2);
+    assert!(groups[0].all_features);
+    assert!(groups[0].enables_platform_filtering());
+    assert_eq!(groups[1].features, ["a"]);
+    assert!(groups.iter().all(|g| g.keep_crates == r.keep_crates));
+    let filter = json!({ "host-platforms": ["x86_64-unknown-linux-gnu"], "groups": [ { "tier": "1" }, { "tier": "1", "host-platforms": ["aarch64-unknown-linux-gnu"] } ] });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    let groups = r.groups().unwrap();
+    assert_eq!(groups[0].host_platforms, r.host_platforms);
+    assert_eq!(
+        groups[1].host_platforms.as_ref().unwrap().first().unwrap(),
+        "aarch64-unknown-linux-gnu"
+    );
+    let filter = json!({ "groups": [ { "tier": "1" } ], "keep-dep-kinds": "normal" });
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    assert!(r.groups().is_err());
+    let filter = json!({ "exclude-crates": [ "openssl-src", "*-src" ]});
+    let r: VendorFilter = serde_json::from_value(filter).unwrap();
+    let patterns = r.exclude_crate_patterns().unwrap();
+    assert!(patterns.iter().any(|p| p.matches("openssl-src")));
+    assert!(patterns.iter().any(|p| p.matches("libz-src")));
+    assert!(!patterns.iter().any(|p| p.matches("libz-sys")));
 }
 
 #[test]
@@ -1137,24 +2248,60 @@ fn test_platforms() {
         "wasm32-wasi",
         "x86_64-sun-solaris",
     ];
-    let target_list: Vec<(&str, ParsedPlatform)> = targets
-        .iter()
-        .map(|platform| (*platform, platform.split('-').collect()))
-        .collect();
+    let expand = |platforms: &[&str], exclude: &[&str]| {
+        expand_platforms(platforms, exclude, &targets, &targets)
+    };
     // Verify we pass through literals
     for &target in targets.iter() {
-        let targets = [target];
-        let v = expand_platforms(targets.as_slice(), &target_list).unwrap();
-        assert_eq!(v.len(), 1);
-        assert_eq!(v[0], target);
-    }
-
-    let linux_spec = ["*-unknown-linux-gnu"];
-    let mut linuxes = expand_platforms(&linux_spec, &target_list).unwrap();
-    linuxes.sort();
-    assert_eq!(linuxes.len(), 2);
-    assert_eq!(linuxes[0], "powerpc64le-unknown-linux-gnu");
-    assert_eq!(linuxes[1], "x86_64-unknown-linux-gnu");
+        let v = expand(&[target], &[]).unwrap();
+        assert_eq!(v, [target]);
+    }
+
+    let linuxes = expand(&["*-unknown-linux-gnu"], &[]).unwrap();
+    assert_eq!(
+        linuxes,
+        ["powerpc64le-unknown-linux-gnu", "x86_64-unknown-linux-gnu"]
+    );
+    // Patterns match the whole triple, regardless of the number of components
+    let linuxes = expand(&["*-linux-gnu"], &[]).unwrap();
+    assert_eq!(
+        linuxes,
+        ["powerpc64le-unknown-linux-gnu", "x86_64-unknown-linux-gnu"]
+    );
+    let v = expand(&["x86_64-*"], &[]).unwrap();
+    assert_eq!(
+        v,
+        [
+            "x86_64-sun-solaris",
+            "x86_64-unknown-linux-gnu",
+            "x86_64-unknown-linux-gnux32"
+        ]
+    );
+    let v = expand(&["*linux*"], &["*-gnu*"]).unwrap();
+    assert_eq!(v, ["arm-linux-androideabi"]);
+    let v = expand(&["wasm32-w?si", "mips[a-z]*-*"], &[]).unwrap();
+    assert_eq!(v, ["mipsel-sony-psp", "wasm32-wasi"]);
+
+    let linux_spec = ["*-unknown-linux-*", "x86_64-unknown-linux-gnu"];
+    let linuxes = expand(&linux_spec, &["*-linux-gnux32", "powerpc*"]).unwrap();
+    assert_eq!(linuxes, ["x86_64-unknown-linux-gnu"]);
+    let all: Vec<_> = targets.to_vec();
+    let v = subtract_platforms(all, &["*-linux-*", "wasm32-*"]).unwrap();
+    assert_eq!(v, ["mipsel-sony-psp", "x86_64-sun-solaris"]);
+    assert!(subtract_platforms(vec![], &["[x86_64"]).is_err());
+
+    // Patterns matching nothing and unknown literals are errors
+    assert!(expand(&["*-linux-musl"], &[]).is_err());
+    assert!(expand(&["x86_64-unknown-linux-musl"], &[]).is_err());
+    assert!(expand(&["[x86_64"], &[]).is_err());
+    // Patterns only expand to the (filtered) target list, literals may be any known target
+    let v = expand_platforms(&["x86_64-*"], &[], &["x86_64-sun-solaris"], &targets).unwrap();
+    assert_eq!(v, ["x86_64-sun-solaris"]);
+    let v = expand_platforms(&["wasm32-wasi"], &[], &[], &targets).unwrap();
+    assert_eq!(v, ["wasm32-wasi"]);
+    // Custom target specifications are not validated here
+    let v = expand(&["targets/x86_64-acme-linux.json"], &[]).unwrap();
+    assert_eq!(v, ["targets/x86_64-acme-linux.json"]);
 }
 
 #[test]
@@ -1241,6 +2388,32 @@ targets = ["x86_64-unknown-linux-gnu"]
     assert!(table.get("lib").is_some());
 }
 
+#[test]
+fn test_filter_manifest_proc_macro() {
+    let mut v: toml::Value = toml::from_str(
+        r#"
+[package]
+name = "serde_derive"
+
+[lib]
+name = "serde_derive"
+proc-macro = true
+"#,
+    )
+    .unwrap();
+    filter_manifest(&mut v);
+    assert_eq!(v["lib"]["proc-macro"].as_bool(), Some(true));
+    assert_eq!(v["lib"]["path"].as_str(), Some(STUB_LIBRS));
+    let stub = stub_compile_error(
+        "foo",
+        r#"not needed for platforms ["x86_64-unknown-linux-gnu"]"#,
+    );
+    assert_eq!(
+        stub.trim_end(),
+        r#"compile_error!("crate foo was removed by cargo-vendor-filterer: not needed for platforms [\"x86_64-unknown-linux-gnu\"]");"#
+    );
+}
+
 #[test]
 fn test_cli() {
     use clap::CommandFactory;
@@ -1269,17 +2442,136 @@ fn test_find_glob_matches() {
     assert!(matches.is_empty());
 }
 
+/// Write the files of a vendored crate, containing their own path, along
+/// with their checksums.
+#[cfg(test)]
+fn write_test_crate(base_path: &Utf8Path, files: &[&str]) {
+    let mut checksums = CargoChecksums {
+        files: BTreeMap::new(),
+        package: None,
+    };
+    for &f in files {
+        let p = base_path.join(f);
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        if !p.exists() {
+            std::fs::write(&p, f).unwrap();
+        }
+        checksums.files.insert(f.to_string(), "0".repeat(64));
+    }
+    let checksums_path = base_path.join(CARGO_CHECKSUM);
+    std::fs::write(checksums_path, serde_json::to_string(&checksums).unwrap()).unwrap();
+}
+
+#[test]
+fn test_process_crate_paths() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let base_path = Utf8Path::from_path(temp_dir.path()).unwrap();
+    let files = [
+        "Cargo.toml",
+        "build.rs",
+        "README.md",
+        "src/lib.rs",
+        "src/zlib/zlib.c",
+        "src/zlib/zconf.h",
+        "src/zlib/contrib/inflate.c",
+    ];
+    write_test_crate(base_path, &files);
+    let checksums_path = base_path.join(CARGO_CHECKSUM);
+
+    let filter = CratePaths {
+        excludes: BTreeSet::from(["src/zlib", "!src/zlib/*.h"]),
+        keep: BTreeSet::from(["build.rs", "src"]),
+        ..Default::default()
+    };
+    process_crate_paths(base_path, "libz-sys", &filter).unwrap();
+    let remaining: BTreeSet<_> = files
+        .into_iter()
+        .filter(|f| base_path.join(f).exists())
+        .collect();
+    assert_eq!(
+        remaining,
+        BTreeSet::from(["Cargo.toml", "build.rs", "src/lib.rs", "src/zlib/zconf.h"])
+    );
+    assert!(!base_path.join("src/zlib/contrib").exists());
+    let checksums: CargoChecksums =
+        serde_json::from_str(&std::fs::read_to_string(&checksums_path).unwrap()).unwrap();
+    assert!(checksums.files.keys().map(|k| k.as_str()).eq(remaining));
+}
+
+#[test]
+fn test_process_crate_paths_presets() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let base_path = Utf8Path::from_path(temp_dir.path()).unwrap();
+    std::fs::write(
+        base_path.join(CARGO_TOML),
+        "[package]\nname = \"foo\"\n\n[[test]]\nname = \"api\"\npath = \"tests/api.rs\"\n",
+    )
+    .unwrap();
+    std::fs::create_dir(base_path.join("src")).unwrap();
+    std::fs::write(
+        base_path.join("src/lib.rs"),
+        "#![doc = include_str!(\"../docs/intro.md\")]\n",
+    )
+    .unwrap();
+    let files = [
+        "Cargo.toml",
+        "CHANGELOG.md",
+        "README.md",
+        ".github/workflows/ci.yml",
+        "docs/intro.md",
+        "docs/design.md",
+        "src/lib.rs",
+        "tests/api.rs",
+        "tests/common/mod.rs",
+        "fuzz/Cargo.toml",
+    ];
+    write_test_crate(base_path, &files);
+
+    let filter = CratePaths {
+        presets: BTreeSet::from_iter(
+            <presets::ExcludePreset as clap::ValueEnum>::value_variants()
+                .iter()
+                .copied(),
+        ),
+        ..Default::default()
+    };
+    process_crate_paths(base_path, "foo", &filter).unwrap();
+    let remaining: BTreeSet<_> = files
+        .into_iter()
+        .filter(|f| base_path.join(f).exists())
+        .collect();
+    // The test target is kept, as cargo would fail to load the manifest without it,
+    // and the included documentation, as the crate would fail to compile without it
+    assert_eq!(
+        remaining,
+        BTreeSet::from([
+            "Cargo.toml",
+            "README.md",
+            "docs/intro.md",
+            "src/lib.rs",
+            "tests/api.rs"
+        ])
+    );
+}
+
 #[test]
 fn test_crate_exclude_glob_parsing() {
     let exclude = CrateExclude::parse_str("mylib#src/lib.rs").unwrap();
-    assert_eq!(exclude.name, "mylib");
+    assert_eq!(exclude.name.to_string(), "mylib");
     assert_eq!(exclude.exclude, "src/lib.rs");
 
     let exclude = CrateExclude::parse_str("mylib#src/*.rs").unwrap();
-    assert_eq!(exclude.name, "mylib");
+    assert_eq!(exclude.name.to_string(), "mylib");
     assert_eq!(exclude.exclude, "src/*.rs");
 
     let exclude = CrateExclude::parse_str("*#tests/*").unwrap();
-    assert_eq!(exclude.name, "*");
+    assert_eq!(exclude.name.to_string(), "*");
     assert_eq!(exclude.exclude, "tests/*");
+
+    let exclude = CrateExclude::parse_str("libz-sys@^1.1#src/zlib").unwrap();
+    assert!(exclude.name.matches("libz-sys", "1.1.20"));
+    assert!(!exclude.name.matches("libz-sys", "1.0.25"));
+    assert_eq!(exclude.exclude, "src/zlib");
+
+    assert!(CrateExclude::parse_str("libz-sys@1.x.y#src/zlib").is_err());
 }
@@ -0,0 +1,316 @@
+use crate::VendorFilter;
+use anyhow::{anyhow, Context, Result};
+use cargo_metadata::{DepKindInfo, Package, PackageId};
+use clap::{builder::PossibleValue, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+/// What to do with crates whose license does not satisfy the policy.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LicensePolicy {
+    /// Fail the run, listing the offending crates.
+    #[default]
+    Fail,
+    /// Replace offending crates with stubs when they are only reachable
+    /// through optional dependencies; fail otherwise.
+    Stub,
+}
+
+impl ValueEnum for LicensePolicy {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Fail, Self::Stub]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Fail => PossibleValue::new("fail"),
+            Self::Stub => PossibleValue::new("stub"),
+        })
+    }
+}
+
+/// A parsed SPDX license expression.
+#[derive(Debug, PartialEq, Eq)]
+enum LicenseExpr {
+    /// A license identifier, optionally with an exception (`X WITH Y`).
+    License(String, Option<String>),
+    And(Box<LicenseExpr>, Box<LicenseExpr>),
+    Or(Box<LicenseExpr>, Box<LicenseExpr>),
+}
+
+impl LicenseExpr {
+    /// Parse an SPDX license expression.  The legacy `/` separator used by
+    /// older crates (e.g. `MIT/Apache-2.0`) is accepted as an alias for `OR`.
+    fn parse(s: &str) -> Result<Self> {
+        let spaced = s
+            .replace('(', " ( ")
+            .replace(')', " ) ")
+            .replace('/', " OR ");
+        let mut tokens: VecDeque<&str> = spaced.split_whitespace().collect();
+        let r = Self::parse_or(&mut tokens).with_context(|| format!("Parsing license {s:?}"))?;
+        if let Some(t) = tokens.front() {
+            anyhow::bail!("Unexpected token {t:?} in license {s:?}");
+        }
+        Ok(r)
+    }
+
+    fn parse_or(tokens: &mut VecDeque<&str>) -> Result<Self> {
+        let mut r = Self::parse_and(tokens)?;
+        while tokens.front().is_some_and(|t| t.eq_ignore_ascii_case("OR")) {
+            tokens.pop_front();
+            r = Self::Or(Box::new(r), Box::new(Self::parse_and(tokens)?));
+        }
+        Ok(r)
+    }
+
+    fn parse_and(tokens: &mut VecDeque<&str>) -> Result<Self> {
+        let mut r = Self::parse_primary(tokens)?;
+        while tokens
+            .front()
+            .is_some_and(|t| t.eq_ignore_ascii_case("AND"))
+        {
+            tokens.pop_front();
+            r = Self::And(Box::new(r), Box::new(Self::parse_primary(tokens)?));
+        }
+        Ok(r)
+    }
+
+    fn parse_primary(tokens: &mut VecDeque<&str>) -> Result<Self> {
+        match tokens.pop_front() {
+            Some("(") => {
+                let r = Self::parse_or(tokens)?;
+                match tokens.pop_front() {
+                    Some(")") => Ok(r),
+                    _ => anyhow::bail!("Missing closing parenthesis"),
+                }
+            }
+            Some(t)
+                if t == ")"
+                    || ["AND", "OR", "WITH"]
+                        .iter()
+                        .any(|o| t.eq_ignore_ascii_case(o)) =>
+            {
+                anyhow::bail!("Unexpected token {t:?}")
+            }
+            Some(id) => {
+                let exception = if tokens
+                    .front()
+                    .is_some_and(|t| t.eq_ignore_ascii_case("WITH"))
+                {
+                    tokens.pop_front();
+                    let e = tokens
+                        .pop_front()
+                        .ok_or_else(|| anyhow!("Missing exception after WITH"))?;
+                    Some(e.to_string())
+                } else {
+                    None
+                };
+                Ok(Self::License(id.to_string(), exception))
+            }
+            None => anyhow::bail!("Unexpected end of expression"),
+        }
+    }
+
+    /// Evaluate the expression: an `OR` is satisfied by either side, an `AND`
+    /// requires both sides.
+    fn satisfies(&self, policy: &LicenseFilter) -> bool {
+        match self {
+            Self::License(id, exception) => policy.accepts(id, exception.as_deref()),
+            Self::And(a, b) => a.satisfies(policy) && b.satisfies(policy),
+            Self::Or(a, b) => a.satisfies(policy) || b.satisfies(policy),
+        }
+    }
+}
+
+/// The allowed and denied license identifiers from the configuration.
+struct LicenseFilter<'a> {
+    allowed: Option<&'a BTreeSet<String>>,
+    denied: Option<&'a BTreeSet<String>>,
+}
+
+impl LicenseFilter<'_> {
+    /// A license with an exception matches either `X WITH Y` or just `X`.
+    fn accepts(&self, id: &str, exception: Option<&str>) -> bool {
+        let full = exception.map(|e| format!("{id} WITH {e}"));
+        let contains = |set: &BTreeSet<String>| {
+            set.contains(id) || full.as_ref().is_some_and(|f| set.contains(f))
+        };
+        let allowed = self.allowed.is_none_or(contains);
+        let denied = self.denied.is_some_and(contains);
+        allowed && !denied
+    }
+
+    /// Returns a reason if the package is not acceptable.
+    fn check(&self, package: &Package) -> Option<String> {
+        match package.license.as_deref() {
+            Some(license) => match LicenseExpr::parse(license) {
+                Ok(expr) if expr.satisfies(self) => None,
+                Ok(_) => Some(license.to_string()),
+                Err(e) => Some(format!("{e:#}")),
+            },
+            // Without an expression we can only reject when there is an allowlist.
+            None if self.allowed.is_some() => Some(match package.license_file.as_ref() {
+                Some(f) => format!("no SPDX license expression, license-file {f}"),
+                None => "no license".to_string(),
+            }),
+            None => None,
+        }
+    }
+}
+
+/// A crate which does not satisfy the license policy.
+struct Violation {
+    id: PackageId,
+    reason: String,
+    path: Option<String>,
+}
+
+/// Check the licenses of the kept (non-local) packages against the allowed and
+/// denied license lists.  Depending on the [`LicensePolicy`], either fail
+/// or remove the offending packages from `packages` so they are replaced with
+/// stubs.  `dependency_path` describes how a package was selected, and `required`
+/// computes the packages which are required through non-optional dependencies
+/// with the same filtering as `packages`, and cannot be stubbed.  Returns the
+/// names of the crates that were removed.
+pub(crate) fn check_licenses(
+    config: &VendorFilter,
+    packages: &mut HashMap<PackageId, &Package>,
+    dependency_path: impl Fn(&PackageId) -> Option<String>,
+    required: impl FnOnce() -> Result<HashSet<PackageId>>,
+) -> Result<BTreeSet<String>> {
+    let filter = LicenseFilter {
+        allowed: config.allowed_licenses.as_ref(),
+        denied: config.denied_licenses.as_ref(),
+    };
+    if filter.allowed.is_none() && filter.denied.is_none() {
+        return Ok(BTreeSet::new());
+    }
+
+    let mut violations: Vec<_> = packages
+        .values()
+        .filter(|pkg| pkg.source.is_some())
+        .filter_map(|pkg| {
+            filter.check(pkg).map(|reason| Violation {
+                id: pkg.id.clone(),
+                reason,
+                path: dependency_path(&pkg.id),
+            })
+        })
+        .collect();
+    if violations.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+    violations.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let policy = config.license_policy.unwrap_or_default();
+    let required = match policy {
+        LicensePolicy::Stub => required()?,
+        LicensePolicy::Fail => HashSet::new(),
+    };
+    let mut stubbed = BTreeSet::new();
+    let mut errors = Vec::new();
+    for v in violations {
+        let package = packages[&v.id];
+        let name = format!("{} {}", package.name, package.version);
+        if policy == LicensePolicy::Stub && !required.contains(&v.id) {
+            packages.remove(&v.id);
+            stubbed.insert(name);
+            continue;
+        }
+        let path = v.path.as_deref().unwrap_or("no dependency path found");
+        errors.push(format!("  {name} ({}): {path}", v.reason));
+    }
+    if !errors.is_empty() {
+        anyhow::bail!(
+            "License policy violated by {} crate(s):\n{}",
+            errors.len(),
+            errors.join("\n")
+        );
+    }
+    Ok(stubbed)
+}
+
+/// Returns true if the dependency of `parent` on `dep` is optional for the kind
+/// and platform of an edge of the resolve graph.  An edge is optional if every
+/// declaration of it in the parent manifest for the same kind and platform is
+/// optional; e.g. a dependency may be optional in `[dependencies]`, but not in
+/// `[dev-dependencies]` or for another platform.
+pub(crate) fn is_optional_dependency(parent: &Package, dep: &Package, kind: &DepKindInfo) -> bool {
+    let mut declarations = parent.dependencies.iter().filter(|d| d.name == dep.name);
+    let mut matching = declarations
+        .clone()
+        .filter(|d| d.kind == kind.kind && d.target == kind.target)
+        .peekable();
+    // Fall back to all declarations if cargo reports the edge differently
+    if matching.peek().is_some() {
+        matching.all(|d| d.optional)
+    } else {
+        declarations.all(|d| d.optional)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(v: &[&str]) -> BTreeSet<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_license() {
+        use LicenseExpr::*;
+        let lic = |s: &str| Box::new(License(s.to_string(), None));
+        assert_eq!(
+            LicenseExpr::parse("MIT OR Apache-2.0").unwrap(),
+            Or(lic("MIT"), lic("Apache-2.0"))
+        );
+        assert_eq!(
+            LicenseExpr::parse("MIT/Apache-2.0").unwrap(),
+            Or(lic("MIT"), lic("Apache-2.0"))
+        );
+        assert_eq!(
+            LicenseExpr::parse("(MIT OR Apache-2.0) AND Unicode-3.0").unwrap(),
+            And(
+                Box::new(Or(lic("MIT"), lic("Apache-2.0"))),
+                lic("Unicode-3.0")
+            )
+        );
+        assert_eq!(
+            LicenseExpr::parse("Apache-2.0 WITH LLVM-exception").unwrap(),
+            License("Apache-2.0".into(), Some("LLVM-exception".into()))
+        );
+        for invalid in ["", "MIT OR", "(MIT", "MIT)", "AND MIT", "MIT WITH"] {
+            assert!(LicenseExpr::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_license_filter() {
+        let allowed = set(&["MIT", "Apache-2.0"]);
+        let denied = set(&["GPL-3.0-only"]);
+        let filter = LicenseFilter {
+            allowed: Some(&allowed),
+            denied: Some(&denied),
+        };
+        let ok = |s: &str| LicenseExpr::parse(s).unwrap().satisfies(&filter);
+        assert!(ok("MIT"));
+        assert!(ok("MIT OR Apache-2.0"));
+        assert!(ok("Unlicense OR MIT"));
+        assert!(ok("Apache-2.0 WITH LLVM-exception"));
+        assert!(!ok("Unlicense"));
+        assert!(!ok("MIT AND Unlicense"));
+        assert!(!ok("GPL-3.0-only"));
+        assert!(ok("GPL-3.0-only OR MIT"));
+
+        let filter = LicenseFilter {
+            allowed: None,
+            denied: Some(&denied),
+        };
+        let ok = |s: &str| LicenseExpr::parse(s).unwrap().satisfies(&filter);
+        assert!(ok("Unlicense"));
+        assert!(!ok("GPL-3.0-only"));
+        assert!(!ok("MIT AND GPL-3.0-only"));
+    }
+}
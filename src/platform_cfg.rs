@@ -0,0 +1,205 @@
+use anyhow::{anyhow, Context, Result};
+use cargo_platform::{Cfg, CfgExpr, Platform};
+use std::collections::HashSet;
+use std::process::Command;
+use std::str::FromStr;
+
+/// Parse a cfg expression, either bare (`unix`) or wrapped (`cfg(unix)`).
+pub(crate) fn parse_cfg_expr(s: &str) -> Result<CfgExpr> {
+    let s = s.trim();
+    let inner = s
+        .strip_prefix("cfg(")
+        .and_then(|s| s.strip_suffix(')'))
+        .unwrap_or(s);
+    CfgExpr::from_str(inner).with_context(|| format!("Invalid cfg expression: {s}"))
+}
+
+/// Parse the output of `rustc --print cfg`.
+pub(crate) fn parse_cfg_list(buf: &str) -> Result<Vec<Cfg>> {
+    buf.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .map(|l| Cfg::from_str(l).with_context(|| format!("Invalid cfg: {l}")))
+        .collect()
+}
+
+/// Gather the cfg values of a target via `rustc --print cfg --target`.
+pub(crate) fn rustc_target_cfg(target: &str) -> Result<Vec<Cfg>> {
+    let o = Command::new("rustc")
+        .args(["--print", "cfg", "--target", target])
+        .output()
+        .context("Failed to invoke rustc --print cfg")?;
+    if !o.status.success() {
+        anyhow::bail!(
+            "rustc --print cfg --target {target} failed: {}",
+            String::from_utf8_lossy(&o.stderr).trim()
+        );
+    }
+    parse_cfg_list(&String::from_utf8(o.stdout)?)
+}
+
+/// Something the `target` of a dependency can be evaluated against.
+pub(crate) trait MatchPlatform {
+    /// Returns true if a dependency for this platform is enabled.
+    fn matches(&self, platform: &Platform) -> bool;
+}
+
+/// A target known to rustc, along with its cfg values.
+#[derive(Debug)]
+pub(crate) struct TargetCfg {
+    name: String,
+    cfg: Vec<Cfg>,
+}
+
+impl TargetCfg {
+    /// Gather the cfg values of several targets from rustc.
+    pub(crate) fn for_targets<'a>(
+        targets: impl IntoIterator<Item = &'a String>,
+    ) -> Result<Vec<Self>> {
+        let targets: Vec<_> = targets.into_iter().collect();
+        rustc_targets_cfg(&targets)
+            .into_iter()
+            .map(|(target, cfg)| {
+                Ok(Self {
+                    name: target.clone(),
+                    cfg: cfg?,
+                })
+            })
+            .collect()
+    }
+
+    /// The name of the target.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl MatchPlatform for TargetCfg {
+    fn matches(&self, platform: &Platform) -> bool {
+        platform.matches(&self.name, &self.cfg)
+    }
+}
+
+/// Parse the host target from the output of `rustc -vV`.
+fn parse_rustc_host(buf: &str) -> Option<&str> {
+    buf.lines()
+        .find_map(|l| l.strip_prefix("host: "))
+        .map(str::trim)
+}
+
+/// Return the host target of the running rustc.
+pub(crate) fn rustc_host() -> Result<String> {
+    let o = Command::new("rustc")
+        .arg("-vV")
+        .output()
+        .context("Failed to invoke rustc -vV")?;
+    if !o.status.success() {
+        anyhow::bail!(
+            "rustc -vV failed ({}): {}",
+            o.status,
+            String::from_utf8_lossy(&o.stderr).trim()
+        );
+    }
+    let buf = String::from_utf8(o.stdout)?;
+    parse_rustc_host(&buf)
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| anyhow!("Failed to find host in output of rustc -vV"))
+}
+
+/// Gather the cfg values of many targets via `rustc --print cfg --target`.
+fn rustc_targets_cfg<'a>(targets: &[&'a String]) -> Vec<(&'a String, Result<Vec<Cfg>>)> {
+    // Invoking rustc for each target is slow, so do it in parallel.
+    let jobs = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = targets.len().div_ceil(jobs).max(1);
+    std::thread::scope(|s| {
+        let handles: Vec<_> = targets
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|&t| (t, rustc_target_cfg(t)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("rustc cfg thread panicked"))
+            .collect()
+    })
+}
+
+/// Return the targets whose cfg values satisfy the expression.
+pub(crate) fn filter_targets_by_cfg(
+    targets: &HashSet<String>,
+    expr: &CfgExpr,
+) -> Result<HashSet<String>> {
+    let targets: Vec<_> = targets.iter().collect();
+    let mut r = HashSet::new();
+    for (target, cfg) in rustc_targets_cfg(&targets) {
+        match cfg {
+            Ok(cfg) if expr.matches(&cfg) => {
+                r.insert(target.clone());
+            }
+            Ok(_) => {}
+            // Not all targets (e.g. from the tier lists) are known to every rustc
+            Err(e) => eprintln!("Warning: Skipping target {target}: {e:#}"),
+        }
+    }
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cfg_expr_matches() {
+        let linux = parse_cfg_list(
+            r#"
+debug_assertions
+target_arch="x86_64"
+target_env="gnu"
+target_family="unix"
+target_os="linux"
+target_pointer_width="64"
+unix
+"#,
+        )
+        .unwrap();
+        let macos = parse_cfg_list(
+            r#"
+target_arch="aarch64"
+target_family="unix"
+target_os="macos"
+target_pointer_width="64"
+unix
+"#,
+        )
+        .unwrap();
+        let expr = parse_cfg_expr(r#"cfg(all(unix, not(target_os = "macos")))"#).unwrap();
+        assert!(expr.matches(&linux));
+        assert!(!expr.matches(&macos));
+        let expr = parse_cfg_expr(r#"target_pointer_width = "64""#).unwrap();
+        assert!(expr.matches(&linux));
+        assert!(expr.matches(&macos));
+        assert!(parse_cfg_expr("cfg(all(unix").is_err());
+    }
+
+    #[test]
+    fn test_target_cfg_matches() {
+        let linux = TargetCfg {
+            name: "x86_64-unknown-linux-gnu".into(),
+            cfg: parse_cfg_list("target_os=\"linux\"\nunix\n").unwrap(),
+        };
+        let platform = |s: &str| Platform::from_str(s).unwrap();
+        assert!(linux.matches(&platform("x86_64-unknown-linux-gnu")));
+        assert!(linux.matches(&platform("cfg(unix)")));
+        assert!(!linux.matches(&platform("cfg(windows)")));
+        assert!(!linux.matches(&platform("aarch64-unknown-linux-gnu")));
+        let buf = "rustc 1.82.0 (f6e511eec 2024-10-15)\nbinary: rustc\nhost: x86_64-unknown-linux-gnu\nrelease: 1.82.0\n";
+        assert_eq!(parse_rustc_host(buf), Some("x86_64-unknown-linux-gnu"));
+        assert_eq!(parse_rustc_host(""), None);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{CARGO_TOML, MANIFEST_KEY_PACKAGE};
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{builder::PossibleValue, ValueEnum};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+/// A named list of paths commonly shipped in crates but not needed to build them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExcludePreset {
+    Tests,
+    Benches,
+    Examples,
+    Docs,
+    Ci,
+    Fuzz,
+}
+
+impl ExcludePreset {
+    /// The glob patterns the preset expands to, relative to the crate root.
+    pub(crate) fn patterns(self) -> &'static [&'static str] {
+        match self {
+            Self::Tests => &["tests"],
+            Self::Benches => &["benches"],
+            Self::Examples => &["examples"],
+            // README files are left alone, as crates commonly `include_str!` them;
+            // other included files are kept by `included_paths`.
+            Self::Docs => &[
+                "doc",
+                "docs",
+                "CHANGELOG*",
+                "CHANGES*",
+                "HISTORY*",
+                "RELEASES*",
+                "CONTRIBUTING*",
+                "CODE_OF_CONDUCT*",
+            ],
+            Self::Ci => &[
+                ".github",
+                ".gitlab-ci.yml",
+                ".circleci",
+                ".cirrus.yml",
+                ".travis.yml",
+                "appveyor.yml",
+                ".appveyor.yml",
+                "azure-pipelines.yml",
+                "ci",
+            ],
+            Self::Fuzz => &["fuzz"],
+        }
+    }
+}
+
+impl ValueEnum for ExcludePreset {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Tests,
+            Self::Benches,
+            Self::Examples,
+            Self::Docs,
+            Self::Ci,
+            Self::Fuzz,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        let name = match self {
+            Self::Tests => "tests",
+            Self::Benches => "benches",
+            Self::Examples => "examples",
+            Self::Docs => "docs",
+            Self::Ci => "ci",
+            Self::Fuzz => "fuzz",
+        };
+        Some(PossibleValue::new(name).help(self.patterns().join(", ")))
+    }
+}
+
+/// The directories cargo infers targets from when the manifest lists them
+/// by name only.
+const TARGET_DIRS: &[(&str, &str)] = &[
+    ("bin", "src/bin"),
+    ("example", "examples"),
+    ("test", "tests"),
+    ("bench", "benches"),
+];
+
+/// Return the files referenced by the targets and `build` key of a crate's manifest,
+/// which must be kept for cargo to load it.
+pub(crate) fn manifest_referenced_paths(path: &Utf8Path) -> Result<HashSet<Utf8PathBuf>> {
+    let manifest_path = path.join(CARGO_TOML);
+    let buf = std::fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Reading {manifest_path}"))?;
+    let manifest: toml::Table =
+        toml::from_str(&buf).with_context(|| format!("Parsing {manifest_path}"))?;
+    Ok(referenced_paths(&manifest))
+}
+
+fn referenced_paths(manifest: &toml::Table) -> HashSet<Utf8PathBuf> {
+    let mut r = HashSet::new();
+    let mut add = |p: &str| {
+        r.insert(Utf8Path::new(p.trim_start_matches("./")).to_path_buf());
+    };
+    if let Some(build) = manifest
+        .get(MANIFEST_KEY_PACKAGE)
+        .and_then(|p| p.get("build"))
+        .and_then(|b| b.as_str())
+    {
+        add(build);
+    }
+    if let Some(path) = manifest
+        .get("lib")
+        .and_then(|l| l.get("path"))
+        .and_then(|p| p.as_str())
+    {
+        add(path);
+    }
+    for &(kind, dir) in TARGET_DIRS {
+        let targets = manifest.get(kind).and_then(|t| t.as_array());
+        for target in targets.into_iter().flatten() {
+            if let Some(path) = target.get("path").and_then(|p| p.as_str()) {
+                add(path);
+            } else if let Some(name) = target.get("name").and_then(|n| n.as_str()) {
+                add(&format!("{dir}/{name}.rs"));
+                add(&format!("{dir}/{name}/main.rs"));
+            }
+        }
+    }
+    r
+}
+
+/// The macros including a file relative to the source file using them.
+const INCLUDE_MACROS: &[&str] = &["include_str!", "include_bytes!"];
+
+/// Return the files included with `include_str!` or `include_bytes!` by the
+/// sources in `src`, like `#![doc = include_str!("../docs/intro.md")]`, which
+/// must be kept for the crate to compile.
+pub(crate) fn included_paths(path: &Utf8Path) -> Result<HashSet<Utf8PathBuf>> {
+    let mut r = HashSet::new();
+    let src = path.join("src");
+    if !src.is_dir() {
+        return Ok(r);
+    }
+    for entry in walkdir::WalkDir::new(&src) {
+        let entry = entry?;
+        let file = Utf8Path::from_path(entry.path())
+            .ok_or_else(|| anyhow!("Invalid non-UTF8 path: {:?}", entry.path()))?;
+        if file.extension() != Some("rs") || !entry.file_type().is_file() {
+            continue;
+        }
+        let buf = std::fs::read(file).with_context(|| format!("Reading {file}"))?;
+        let dir = file
+            .strip_prefix(path)?
+            .parent()
+            .unwrap_or(Utf8Path::new(""));
+        for (from_root, included) in find_includes(&String::from_utf8_lossy(&buf)) {
+            let base = if from_root { Utf8Path::new("") } else { dir };
+            r.extend(normalize(&base.join(included.trim_start_matches('/'))));
+        }
+    }
+    Ok(r)
+}
+
+/// Find the string literal arguments of the include macros in Rust source code.
+/// Returns whether each path is relative to the crate root (when built with
+/// `concat!(env!("CARGO_MANIFEST_DIR"), ...)`) rather than the source file.
+fn find_includes(source: &str) -> Vec<(bool, &str)> {
+    let mut r = Vec::new();
+    for &mac in INCLUDE_MACROS {
+        for (i, _) in source.match_indices(mac) {
+            let args = source[i + mac.len()..].trim_start();
+            let Some(args) = args.strip_prefix('(') else {
+                continue;
+            };
+            let args = args.trim_start();
+            let (from_root, args) = match args.strip_prefix("concat!(") {
+                Some(args) => match args.split_once("\"CARGO_MANIFEST_DIR\"") {
+                    Some((_, rest)) => (true, rest.trim_start_matches([' ', ')', ',', '\n'])),
+                    None => continue,
+                },
+                None => (false, args),
+            };
+            if let Some(literal) = args
+                .strip_prefix('"')
+                .and_then(|s| s.split_once('"'))
+                .map(|(literal, _)| literal)
+            {
+                r.push((from_root, literal));
+            }
+        }
+    }
+    r
+}
+
+/// Lexically resolve `..` and `.` components, returning `None` for paths
+/// outside of the crate.
+fn normalize(path: &Utf8Path) -> Option<Utf8PathBuf> {
+    let mut r = Utf8PathBuf::new();
+    for component in path.components() {
+        match component {
+            camino::Utf8Component::Normal(c) => r.push(c),
+            camino::Utf8Component::CurDir => {}
+            camino::Utf8Component::ParentDir => {
+                if !r.pop() {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_referenced_paths() {
+        let manifest: toml::Table = toml::from_str(
+            r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            build = "./build/main.rs"
+
+            [lib]
+            path = "src/lib.rs"
+
+            [[test]]
+            name = "integration"
+            path = "tests/integration.rs"
+
+            [[bench]]
+            name = "speed"
+
+            [[example]]
+            name = "demo"
+            path = "examples/demo.rs"
+        "#,
+        )
+        .unwrap();
+        let paths = referenced_paths(&manifest);
+        for p in [
+            "build/main.rs",
+            "src/lib.rs",
+            "tests/integration.rs",
+            "benches/speed.rs",
+            "benches/speed/main.rs",
+            "examples/demo.rs",
+        ] {
+            assert!(paths.contains(Utf8Path::new(p)), "{p}");
+        }
+        assert_eq!(paths.len(), 6);
+
+        let manifest: toml::Table =
+            toml::from_str("[package]\nname = \"foo\"\nbuild = false\n").unwrap();
+        assert!(referenced_paths(&manifest).is_empty());
+    }
+
+    #[test]
+    fn test_find_includes() {
+        let source = r#"
+            #![doc = include_str!("../docs/intro.md")]
+            const DATA: &[u8] = include_bytes! ( "data/table.bin" );
+            const README: &str =
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/README.md"));
+            include!("generated.rs");
+            let s = include_str!(some_macro!());
+        "#;
+        let mut includes = find_includes(source);
+        includes.sort();
+        assert_eq!(
+            includes,
+            [
+                (false, "../docs/intro.md"),
+                (false, "data/table.bin"),
+                (true, "/README.md")
+            ]
+        );
+        assert_eq!(
+            normalize(Utf8Path::new("src/../docs/./intro.md")).unwrap(),
+            "docs/intro.md"
+        );
+        assert_eq!(normalize(Utf8Path::new("src/../../outside.md")), None);
+    }
+}
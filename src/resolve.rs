@@ -0,0 +1,520 @@
+use crate::platform_cfg::MatchPlatform;
+use crate::{new_metadata_cmd, Args, VendorFilter, CONFIG_KEY, OFFLINE};
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::{
+    CargoOpt::{AllFeatures, NoDefaultFeatures, SomeFeatures},
+    DepKindInfo, DependencyKind, Metadata, MetadataCommand, Node, NodeDep, Package, PackageId,
+};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+
+/// The features to activate when resolving dependencies.
+#[derive(PartialEq, Eq, Hash, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct FeatureSelection {
+    #[serde(default)]
+    pub(crate) all_features: bool,
+    #[serde(default)]
+    pub(crate) no_default_features: bool,
+    #[serde(default)]
+    pub(crate) features: Vec<String>,
+}
+
+/// The workspace members to walk the dependency graph from.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+enum Roots {
+    /// All workspace members
+    All,
+    /// Only the named member
+    Only(String),
+    /// All workspace members except the named ones
+    Except(BTreeSet<String>),
+}
+
+impl Roots {
+    fn contains(&self, name: &str) -> bool {
+        match self {
+            Roots::All => true,
+            Roots::Only(n) => n == name,
+            Roots::Except(names) => !names.contains(name),
+        }
+    }
+}
+
+/// A `cargo metadata` invocation which resolves the dependencies of (a subset of)
+/// the workspace members of a manifest, with a given feature selection.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub(crate) struct Resolution {
+    manifest_path: Option<Utf8PathBuf>,
+    offline: bool,
+    features: FeatureSelection,
+    roots: Roots,
+    /// The entry of `feature-sets` this resolution was created for
+    feature_set: Option<Vec<String>>,
+}
+
+/// The output of a [`Resolution`].
+pub(crate) struct Resolved {
+    pub(crate) metadata: Metadata,
+    /// The workspace members to start from.
+    pub(crate) roots: Vec<PackageId>,
+}
+
+impl Resolution {
+    /// Resolve all workspace members of a manifest with the same features.
+    pub(crate) fn new(
+        manifest_path: Option<&Utf8Path>,
+        offline: bool,
+        features: FeatureSelection,
+    ) -> Self {
+        Self {
+            manifest_path: manifest_path.map(ToOwned::to_owned),
+            offline,
+            features,
+            roots: Roots::All,
+            feature_set: None,
+        }
+    }
+
+    /// Create a copy of this resolution with additional features enabled.
+    pub(crate) fn with_features(&self, features: &[String]) -> Self {
+        let mut r = self.clone();
+        r.features.features.extend(features.iter().cloned());
+        r
+    }
+
+    /// The entry of `feature-sets` this resolution was created for, if any.
+    pub(crate) fn feature_set(&self) -> Option<&[String]> {
+        self.feature_set.as_deref()
+    }
+
+    /// Create the `cargo metadata` invocation, optionally restricted to a single platform.
+    pub(crate) fn metadata_cmd(&self, platform: Option<&str>) -> MetadataCommand {
+        let mut command = MetadataCommand::new();
+        if let Some(p) = self.manifest_path.as_deref() {
+            command.manifest_path(p);
+        }
+        let features = &self.features;
+        if features.all_features {
+            command.features(AllFeatures);
+        }
+        if features.no_default_features {
+            command.features(NoDefaultFeatures);
+        }
+        if !features.features.is_empty() {
+            command.features(SomeFeatures(features.features.clone()));
+        }
+        // Note that `other_options` replaces rather than appends
+        let mut other_options = Vec::new();
+        if self.offline {
+            other_options.push(OFFLINE.to_string());
+        }
+        if let Some(platform) = platform {
+            other_options.push(format!("--filter-platform={platform}"));
+        }
+        command.other_options(other_options);
+        command
+    }
+
+    /// Run `cargo metadata` and find the workspace members to start from.
+    pub(crate) fn exec(&self, platform: Option<&str>) -> Result<Resolved> {
+        let metadata = self
+            .metadata_cmd(platform)
+            .exec()
+            .context("Executing cargo metadata")?;
+        let roots = metadata
+            .workspace_packages()
+            .into_iter()
+            .filter(|p| self.roots.contains(&p.name))
+            .map(|p| p.id.clone())
+            .collect();
+        Ok(Resolved { metadata, roots })
+    }
+}
+
+/// The packages reached by a walk of a resolve graph.
+pub(crate) struct Reached {
+    /// The package each package was first reached from; `None` for the start.
+    parents: HashMap<PackageId, Option<PackageId>>,
+}
+
+impl Reached {
+    /// The reached packages.
+    pub(crate) fn packages(&self) -> impl Iterator<Item = &PackageId> {
+        self.parents.keys()
+    }
+
+    /// Like [`Self::packages`], but consuming the walk.
+    pub(crate) fn into_packages(self) -> impl Iterator<Item = PackageId> {
+        self.parents.into_keys()
+    }
+
+    /// A shortest path of followed edges from the start to the package, if it was reached.
+    pub(crate) fn path(&self, id: &PackageId) -> Option<Vec<&PackageId>> {
+        let (mut id, mut parent) = self.parents.get_key_value(id)?;
+        let mut path = vec![id];
+        while let Some(p) = parent {
+            (id, parent) = self.parents.get_key_value(p)?;
+            path.push(id);
+        }
+        path.reverse();
+        Some(path)
+    }
+}
+
+impl Resolved {
+    /// The nodes of the resolve graph, by package.
+    fn nodes(&self) -> Result<HashMap<&PackageId, &Node>> {
+        let resolve = self
+            .metadata
+            .resolve
+            .as_ref()
+            .ok_or_else(|| anyhow!("Missing dependency graph in cargo metadata"))?;
+        Ok(resolve.nodes.iter().map(|n| (&n.id, n)).collect())
+    }
+
+    /// Walk the resolve graph from the roots, following the edges (from a parent
+    /// package) accepted by `follow`, and return all reached packages (including the roots).
+    pub(crate) fn walk(&self, follow: impl Fn(&PackageId, &NodeDep) -> bool) -> Result<Reached> {
+        self.walk_from(self.roots.iter(), follow)
+    }
+
+    /// Like [`Self::walk`], but starting from the given packages.
+    pub(crate) fn walk_from<'a>(
+        &'a self,
+        start: impl IntoIterator<Item = &'a PackageId>,
+        follow: impl Fn(&PackageId, &NodeDep) -> bool,
+    ) -> Result<Reached> {
+        let nodes = self.nodes()?;
+        let mut parents = HashMap::new();
+        let mut queue: VecDeque<(&PackageId, Option<&PackageId>)> =
+            start.into_iter().map(|id| (id, None)).collect();
+        while let Some((id, parent)) = queue.pop_front() {
+            if parents.contains_key(id) {
+                continue;
+            }
+            parents.insert(id.clone(), parent.cloned());
+            let node = nodes
+                .get(id)
+                .ok_or_else(|| anyhow!("Missing node {id} in dependency graph"))?;
+            let deps = node.deps.iter().filter(|d| follow(id, d));
+            queue.extend(deps.map(|d| (&d.pkg, Some(id))));
+        }
+        Ok(Reached { parents })
+    }
+
+    /// Walk the resolve graph from the roots like cargo would when building for
+    /// `target`: build dependencies and procedural macros, along with everything
+    /// they depend on, are built for (each of) the `hosts` instead.  `self` must be
+    /// resolved for the target, and each host comes with its own resolve graph,
+    /// which is used for the edges of the packages built for it.  Only the edges
+    /// (from a parent package, of a kind) accepted by `follow` whose `target` is
+    /// enabled are followed.
+    pub(crate) fn walk_for_target(
+        &self,
+        target: &dyn MatchPlatform,
+        hosts: &[(&Resolved, &dyn MatchPlatform)],
+        follow: impl Fn(&PackageId, &NodeDep, &DepKindInfo) -> bool,
+    ) -> Result<Reached> {
+        let target_nodes = self.nodes()?;
+        let host_nodes = hosts
+            .iter()
+            .map(|(resolved, _)| resolved.nodes())
+            .collect::<Result<Vec<_>>>()?;
+        let proc_macros: HashSet<_> = std::iter::once(self)
+            .chain(hosts.iter().map(|(resolved, _)| *resolved))
+            .flat_map(|resolved| resolved.metadata.packages.iter())
+            .filter(|p| is_proc_macro(p))
+            .map(|p| &p.id)
+            .collect();
+        // The node of a package built for `None` (the target) or `Some(i)` (the i-th
+        // host).  Packages which are not part of the graph of a host (because they
+        // are only reached through edges for the target) use the target graph.
+        let node = |id: &PackageId, built_for: Option<usize>| {
+            built_for
+                .and_then(|i| host_nodes[i].get(id))
+                .or_else(|| target_nodes.get(id))
+                .copied()
+                .ok_or_else(|| anyhow!("Missing node {id} in dependency graph"))
+        };
+        let platform = |built_for: Option<usize>| match built_for {
+            None => target,
+            Some(i) => hosts[i].1,
+        };
+        let mut seen = HashSet::new();
+        let mut parents = HashMap::new();
+        let mut queue: VecDeque<(&PackageId, Option<usize>, Option<&PackageId>)> =
+            self.roots.iter().map(|id| (id, None, None)).collect();
+        while let Some((id, built_for, parent)) = queue.pop_front() {
+            if !seen.insert((id, built_for)) {
+                continue;
+            }
+            parents.entry(id.clone()).or_insert_with(|| parent.cloned());
+            // Host packages only ever depend on packages for the same host
+            let host_indices: Vec<_> = match built_for {
+                Some(i) => vec![i],
+                None => (0..hosts.len()).collect(),
+            };
+            for dep in node(id, built_for)?.deps.iter() {
+                for kind in dep.dep_kinds.iter().filter(|k| follow(id, dep, k)) {
+                    if kind.kind == DependencyKind::Build {
+                        // Handled below, using the graph of each host
+                        continue;
+                    }
+                    if kind
+                        .target
+                        .as_ref()
+                        .is_none_or(|t| platform(built_for).matches(t))
+                    {
+                        if proc_macros.contains(&dep.pkg) {
+                            let hosts = host_indices.iter().map(|&i| (&dep.pkg, Some(i), Some(id)));
+                            queue.extend(hosts);
+                        } else {
+                            queue.push_back((&dep.pkg, built_for, Some(id)));
+                        }
+                    }
+                }
+            }
+            // Platform specific build dependencies are evaluated for the host,
+            // so cargo may have dropped them from the graph of the target.
+            for &i in host_indices.iter() {
+                for dep in node(id, Some(i))?.deps.iter() {
+                    let enabled = dep.dep_kinds.iter().any(|k| {
+                        k.kind == DependencyKind::Build
+                            && follow(id, dep, k)
+                            && k.target.as_ref().is_none_or(|t| hosts[i].1.matches(t))
+                    });
+                    if enabled {
+                        queue.push_back((&dep.pkg, Some(i), Some(id)));
+                    }
+                }
+            }
+        }
+        Ok(Reached { parents })
+    }
+}
+
+/// Caches the output of resolutions by the platform they are filtered to, as
+/// running `cargo metadata` is slow and the same ones are needed for many platforms.
+#[derive(Default)]
+pub(crate) struct ResolvedCache {
+    resolved: HashMap<(Resolution, Option<String>), Rc<Resolved>>,
+}
+
+impl ResolvedCache {
+    /// Like [`Resolution::exec`], but only runs `cargo metadata` once.
+    pub(crate) fn exec(
+        &mut self,
+        resolution: &Resolution,
+        platform: Option<&str>,
+    ) -> Result<Rc<Resolved>> {
+        let key = (resolution.clone(), platform.map(ToOwned::to_owned));
+        if let Some(resolved) = self.resolved.get(&key) {
+            return Ok(Rc::clone(resolved));
+        }
+        let resolved = Rc::new(resolution.exec(platform)?);
+        self.resolved.insert(key, Rc::clone(&resolved));
+        Ok(resolved)
+    }
+}
+
+/// Returns true if the package is a procedural macro, which is built for the host.
+fn is_proc_macro(package: &Package) -> bool {
+    package
+        .targets
+        .iter()
+        .any(|t| t.kind.iter().any(|k| k == "proc-macro"))
+}
+
+/// The feature selections for all workspace members without their own: one for
+/// each entry of `feature-sets` (added to the common features), or just the common one.
+fn default_feature_selections(
+    config: &VendorFilter,
+) -> Vec<(Option<Vec<String>>, FeatureSelection)> {
+    let common = config.feature_selection();
+    let Some(feature_sets) = config.feature_sets.as_ref() else {
+        return vec![(None, common)];
+    };
+    feature_sets
+        .iter()
+        .map(|set| {
+            let mut features = common.clone();
+            features.features.extend(set.iter().cloned());
+            (Some(set.clone()), features)
+        })
+        .collect()
+}
+
+/// The workspace members selected with `packages` and `exclude-packages`.
+struct MemberSelection {
+    packages: Option<Vec<glob::Pattern>>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl MemberSelection {
+    fn new(config: &VendorFilter) -> Result<Self> {
+        let packages = config
+            .packages
+            .is_some()
+            .then(|| config.package_patterns())
+            .transpose()?;
+        let exclude = config.exclude_package_patterns()?;
+        Ok(Self { packages, exclude })
+    }
+
+    /// Returns true if all workspace members are selected.
+    fn is_all(&self) -> bool {
+        self.packages.is_none() && self.exclude.is_empty()
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.packages
+            .as_ref()
+            .is_none_or(|p| p.iter().any(|p| p.matches(name)))
+            && !self.exclude.iter().any(|p| p.matches(name))
+    }
+}
+
+/// Determine the `cargo metadata` invocations needed to resolve all manifests.
+/// Only the workspace members selected by `packages` and `exclude-packages` are
+/// used as roots.  Members with their own feature selection in `members` are
+/// resolved separately from their own manifest; all others share one invocation
+/// per entry of `feature-sets`.
+pub(crate) fn get_resolutions(args: &Args, config: &VendorFilter) -> Result<Vec<Resolution>> {
+    let default_features = default_feature_selections(config);
+    let selection = MemberSelection::new(config)?;
+    let mut r = Vec::new();
+    let mut unused_members: BTreeSet<_> = config.members.keys().collect();
+    let mut matched_patterns = HashSet::new();
+    for manifest_path in args.get_all_manifest_paths() {
+        if config.members.is_empty() && selection.is_all() {
+            for (feature_set, features) in default_features.iter() {
+                r.push(Resolution {
+                    feature_set: feature_set.clone(),
+                    ..Resolution::new(manifest_path, args.offline, features.clone())
+                });
+            }
+            continue;
+        }
+        let mut command = new_metadata_cmd(manifest_path, args.offline);
+        command.no_deps();
+        let meta = command.exec().context("Executing cargo metadata")?;
+        // The members which are not resolved with the default feature selections
+        let mut customized = BTreeSet::new();
+        for member in meta.workspace_packages() {
+            let name = member.name.as_str();
+            let patterns = selection
+                .packages
+                .iter()
+                .flatten()
+                .chain(&selection.exclude);
+            matched_patterns.extend(patterns.filter(|p| p.matches(name)).map(|p| p.as_str()));
+            unused_members.remove(&member.name);
+            if !selection.contains(name) {
+                customized.insert(member.name.clone());
+                continue;
+            }
+            if let Some(features) = config.members.get(name) {
+                customized.insert(member.name.clone());
+                r.push(Resolution {
+                    manifest_path: Some(member.manifest_path.clone()),
+                    offline: args.offline,
+                    features: features.clone(),
+                    roots: Roots::Only(member.name.clone()),
+                    feature_set: None,
+                });
+            }
+        }
+        if customized.len() < meta.workspace_members.len() {
+            for (feature_set, features) in default_features.iter() {
+                r.push(Resolution {
+                    manifest_path: manifest_path.map(ToOwned::to_owned),
+                    offline: args.offline,
+                    features: features.clone(),
+                    roots: Roots::Except(customized.clone()),
+                    feature_set: feature_set.clone(),
+                });
+            }
+        }
+    }
+    let unmatched = |patterns: &[glob::Pattern]| -> Vec<String> {
+        patterns
+            .iter()
+            .filter(|p| !matched_patterns.contains(p.as_str()))
+            .map(|p| p.to_string())
+            .collect()
+    };
+    let unmatched_packages = unmatched(selection.packages.as_deref().unwrap_or_default());
+    if !unmatched_packages.is_empty() {
+        anyhow::bail!("No workspace member found for packages: {unmatched_packages:?}");
+    }
+    for pattern in unmatched(&selection.exclude) {
+        eprintln!("Warning: No workspace member found for exclude-packages: {pattern}");
+    }
+    if r.is_empty() {
+        anyhow::bail!("No workspace members left after applying exclude-packages");
+    }
+    for name in unused_members {
+        eprintln!("Warning: No workspace member {name} found for metadata.{CONFIG_KEY}.members");
+    }
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roots() {
+        assert!(Roots::All.contains("foo"));
+        assert!(Roots::Only("foo".into()).contains("foo"));
+        assert!(!Roots::Only("foo".into()).contains("bar"));
+        let except = Roots::Except(BTreeSet::from(["foo".to_string()]));
+        assert!(!except.contains("foo"));
+        assert!(except.contains("bar"));
+    }
+
+    #[test]
+    fn test_member_selection() {
+        let selection = MemberSelection::new(&VendorFilter::default()).unwrap();
+        assert!(selection.is_all());
+        assert!(selection.contains("foo"));
+        let config: VendorFilter = serde_json::from_value(serde_json::json!({
+            "packages": ["foo-*"],
+            "exclude-packages": ["foo-test"],
+        }))
+        .unwrap();
+        let selection = MemberSelection::new(&config).unwrap();
+        assert!(!selection.is_all());
+        assert!(selection.contains("foo-cli"));
+        assert!(!selection.contains("foo-test"));
+        assert!(!selection.contains("bar"));
+    }
+
+    #[test]
+    fn test_default_feature_selections() {
+        let config: VendorFilter = serde_json::from_value(serde_json::json!({
+            "features": ["common"],
+        }))
+        .unwrap();
+        let r = default_feature_selections(&config);
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].0, None);
+        assert_eq!(r[0].1.features, ["common"]);
+
+        let config: VendorFilter = serde_json::from_value(serde_json::json!({
+            "features": ["common"],
+            "no-default-features": true,
+            "feature-sets": [["rustls"], ["openssl", "vendored"]],
+        }))
+        .unwrap();
+        let r = default_feature_selections(&config);
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].0.as_deref().unwrap(), ["rustls"]);
+        assert_eq!(r[0].1.features, ["common", "rustls"]);
+        assert_eq!(r[1].1.features, ["common", "openssl", "vendored"]);
+        assert!(r.iter().all(|(_, f)| f.no_default_features));
+    }
+}
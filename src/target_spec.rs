@@ -0,0 +1,147 @@
+use crate::platform_cfg::MatchPlatform;
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path;
+use cargo_platform::{Cfg, Platform};
+use serde::Deserialize;
+
+/// The file extension of custom target specifications.
+const TARGET_SPEC_EXTENSION: &str = "json";
+
+/// The subset of a rustc custom target specification we use.
+/// See <https://doc.rust-lang.org/rustc/targets/custom.html>
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct TargetSpecJson {
+    arch: String,
+    #[serde(default = "default_os")]
+    os: String,
+    #[serde(default)]
+    env: String,
+    #[serde(default = "default_vendor")]
+    vendor: String,
+    #[serde(default)]
+    target_family: Vec<String>,
+    /// This is a string in older specifications and a number in newer ones.
+    target_pointer_width: Option<serde_json::Value>,
+    target_endian: Option<String>,
+}
+
+fn default_os() -> String {
+    "none".into()
+}
+
+fn default_vendor() -> String {
+    "unknown".into()
+}
+
+/// A custom target, loaded from a JSON target specification file.
+#[derive(Debug)]
+pub(crate) struct TargetSpec {
+    /// The target name; like rustc, we use the file stem.
+    name: String,
+    cfg: Vec<Cfg>,
+}
+
+/// Returns true if this platform refers to a target specification file.
+pub(crate) fn is_target_spec(platform: &str) -> bool {
+    Utf8Path::new(platform).extension() == Some(TARGET_SPEC_EXTENSION)
+}
+
+impl TargetSpec {
+    /// Load a target specification file.
+    pub(crate) fn load(path: &Utf8Path) -> Result<Self> {
+        let buf = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading target specification {path}"))?;
+        let name = path
+            .file_stem()
+            .ok_or_else(|| anyhow!("Invalid target specification path {path}"))?;
+        Self::parse(name, &buf).with_context(|| format!("Parsing target specification {path}"))
+    }
+
+    fn parse(name: &str, buf: &str) -> Result<Self> {
+        let spec: TargetSpecJson = serde_json::from_str(buf)?;
+        let keypair = |k: &str, v: &str| Cfg::KeyPair(k.to_string(), v.to_string());
+        let mut cfg = vec![
+            keypair("target_arch", &spec.arch),
+            keypair("target_os", &spec.os),
+            keypair("target_env", &spec.env),
+            keypair("target_vendor", &spec.vendor),
+        ];
+        for family in spec.target_family {
+            // `unix` and `windows` are also available as plain names
+            if family == "unix" || family == "windows" {
+                cfg.push(Cfg::Name(family.clone()));
+            }
+            cfg.push(keypair("target_family", &family));
+        }
+        match spec.target_pointer_width {
+            Some(serde_json::Value::String(w)) => cfg.push(keypair("target_pointer_width", &w)),
+            Some(serde_json::Value::Number(w)) => {
+                cfg.push(keypair("target_pointer_width", &w.to_string()))
+            }
+            Some(o) => anyhow::bail!("Invalid target-pointer-width: {o}"),
+            None => {}
+        }
+        if let Some(endian) = spec.target_endian {
+            cfg.push(keypair("target_endian", &endian));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            cfg,
+        })
+    }
+}
+
+impl MatchPlatform for TargetSpec {
+    fn matches(&self, platform: &Platform) -> bool {
+        // Like cargo, only match the name; the LLVM target is not a platform name
+        platform.matches(&self.name, &self.cfg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_target_spec() {
+        let spec = TargetSpec::parse(
+            "thumbv7em-acme-none",
+            r#"{
+                "llvm-target": "thumbv7em-none-eabihf",
+                "arch": "arm",
+                "target-pointer-width": "32",
+                "data-layout": "e-m:e-p:32:32-Fi8-i64:64-v128:64:128-a:0:32-n32-S64"
+            }"#,
+        )
+        .unwrap();
+        let platform = |s: &str| Platform::from_str(s).unwrap();
+        assert!(spec.matches(&platform("thumbv7em-acme-none")));
+        assert!(!spec.matches(&platform("thumbv7em-none-eabihf")));
+        assert!(spec.matches(&platform(r#"cfg(target_os = "none")"#)));
+        assert!(spec.matches(&platform(r#"cfg(target_pointer_width = "32")"#)));
+        assert!(spec.matches(&platform(r#"cfg(not(unix))"#)));
+        assert!(!spec.matches(&platform("x86_64-unknown-linux-gnu")));
+        assert!(!spec.matches(&platform("cfg(windows)")));
+
+        let spec = TargetSpec::parse(
+            "x86_64-acme-linux",
+            r#"{
+                "llvm-target": "x86_64-unknown-linux-gnu",
+                "arch": "x86_64",
+                "os": "linux",
+                "env": "gnu",
+                "target-family": ["unix"],
+                "target-pointer-width": 64
+            }"#,
+        )
+        .unwrap();
+        assert!(spec.matches(&platform("cfg(unix)")));
+        assert!(spec.matches(&platform(r#"cfg(target_pointer_width = "64")"#)));
+        assert!(!spec.matches(&platform("x86_64-unknown-linux-gnu")));
+        assert!(!spec.matches(&platform("cfg(windows)")));
+        assert!(is_target_spec("targets/x86_64-acme-linux.json"));
+        assert!(!is_target_spec("x86_64-unknown-linux-gnu"));
+    }
+}
@@ -1,73 +1,210 @@
+use std::collections::BTreeMap;
+use std::io::Write;
 use std::str::FromStr;
 
+use anyhow::{Context, Result};
+use camino::Utf8Path;
 use serde::Deserialize;
 
-/// See https://doc.rust-lang.org/nightly/rustc/platform-support.html#tier-1-with-host-tools
-const TIER1: &[&str] = &[
-    "aarch64-unknown-linux-gnu",
-    "i686-pc-windows-gnu",
-    "i686-pc-windows-msvc",
-    "i686-unknown-linux-gnu",
-    "x86_64-apple-darwin",
-    "x86_64-pc-windows-gnu",
-    "x86_64-pc-windows-msvc",
-    "x86_64-unknown-linux-gnu",
-];
-
-/// See https://doc.rust-lang.org/nightly/rustc/platform-support.html#tier-2-with-host-tools
-const TIER2: &[&str] = &[
-    "aarch64-apple-darwin",
-    "aarch64-pc-windows-msvc",
-    "aarch64-unknown-linux-musl",
-    "arm-unknown-linux-gnueabi",
-    "arm-unknown-linux-gnueabihf",
-    "armv7-unknown-linux-gnueabihf",
-    "mips-unknown-linux-gnu",
-    "mips64-unknown-linux-gnuabi64",
-    "mips64el-unknown-linux-gnuabi64",
-    "mipsel-unknown-linux-gnu",
-    "powerpc-unknown-linux-gnu",
-    "powerpc64-unknown-linux-gnu",
-    "powerpc64le-unknown-linux-gnu",
-    "riscv64gc-unknown-linux-gnu",
-    "s390x-unknown-linux-gnu",
-    "wasm32-unknown-unknown",
-    "x86_64-unknown-freebsd",
-    "x86_64-unknown-illumos",
-    "x86_64-unknown-linux-musl",
-    "x86_64-unknown-netbsd",
-];
-
-/// The possible values of select Rust platform "tiers".
-/// There is a third tier, but this API is about limited/curated tiers.
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+/// The embedded tier table, generated from the target metadata of rustc.
+/// See https://doc.rust-lang.org/nightly/rustc/platform-support.html
+const TIER_TABLE: &str = include_str!("tier-table.toml");
+
+/// Suffix for tier selectors which only include targets with host tools.
+const WITH_HOST_TOOLS: &str = "-with-host-tools";
+
+/// The possible values of Rust platform "tiers".
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "u8")]
 pub enum Tier {
-    #[serde(alias = "1")]
     One,
-    #[serde(alias = "2")]
     Two,
+    Three,
 }
 
-impl Tier {
-    /// List the targets for this tier.
-    pub fn targets(&self) -> impl Iterator<Item = &'static str> {
-        match self {
-            Tier::One => either::Left(TIER1.iter()),
-            Tier::Two => either::Right(TIER1.iter().chain(TIER2.iter())),
-        }
-        .copied()
+impl TryFrom<u8> for Tier {
+    type Error = anyhow::Error;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        let r = match v {
+            1 => Self::One,
+            2 => Self::Two,
+            3 => Self::Three,
+            o => anyhow::bail!("Invalid tier {o}"),
+        };
+        Ok(r)
+    }
+}
+
+impl std::fmt::Display for Tier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let v = match self {
+            Tier::One => "1",
+            Tier::Two => "2",
+            Tier::Three => "3",
+        };
+        f.pad(v)
     }
 }
 
-impl FromStr for Tier {
+/// Selects the targets of a tier and all tiers above it, optionally
+/// only those with host tools; e.g. `2` or `2-with-host-tools`.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct TierSelector {
+    tier: Tier,
+    host_tools: bool,
+}
+
+impl FromStr for TierSelector {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let r = match s {
-            "1" => Self::One,
-            "2" => Self::Two,
-            o => anyhow::bail!("Invalid tier {o}"),
+        let (t, host_tools) = match s.strip_suffix(WITH_HOST_TOOLS) {
+            Some(t) => (t, true),
+            None => (s, false),
         };
-        Ok(r)
+        let tier = match t {
+            "1" | "One" => Tier::One,
+            "2" | "Two" => Tier::Two,
+            "3" | "Three" => Tier::Three,
+            _ => anyhow::bail!("Invalid tier {s}"),
+        };
+        Ok(Self { tier, host_tools })
+    }
+}
+
+impl TryFrom<String> for TierSelector {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Support information for a single target.
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct TargetInfo {
+    pub tier: Tier,
+    pub host_tools: Option<bool>,
+    pub std: Option<bool>,
+}
+
+/// The table of targets and their support tiers.
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TierTable {
+    targets: BTreeMap<String, TargetInfo>,
+}
+
+impl TierTable {
+    /// Load the tier table from the given path, or use the embedded one.
+    pub fn load(path: Option<&Utf8Path>) -> Result<Self> {
+        match path {
+            Some(path) => {
+                let buf = std::fs::read_to_string(path)
+                    .with_context(|| format!("Reading tier table {path}"))?;
+                Self::parse(&buf).with_context(|| format!("Parsing tier table {path}"))
+            }
+            None => Self::parse(TIER_TABLE).context("Parsing embedded tier table"),
+        }
+    }
+
+    fn parse(buf: &str) -> Result<Self> {
+        Ok(toml::from_str(buf)?)
+    }
+
+    /// List the targets selected by the tier selector.
+    pub fn targets<'a>(&'a self, selector: &'a TierSelector) -> impl Iterator<Item = &'a str> {
+        self.targets
+            .iter()
+            .filter(|(_, info)| info.tier <= selector.tier)
+            .filter(|(_, info)| !selector.host_tools || info.host_tools == Some(true))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Print the table in a human readable form.
+    pub fn print(&self, mut w: impl Write) -> Result<()> {
+        let yes_no = |v: Option<bool>| match v {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "?",
+        };
+        let width = self
+            .targets
+            .keys()
+            .map(|k| k.len())
+            .max()
+            .unwrap_or_default();
+        writeln!(w, "{:width$}  TIER  HOST-TOOLS  STD", "TARGET")?;
+        for (name, info) in self.targets.iter() {
+            writeln!(
+                w,
+                "{name:width$}  {:4}  {:10}  {}",
+                info.tier,
+                yes_no(info.host_tools),
+                yes_no(info.std)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_tier_table() {
+        let table = TierTable::load(None).unwrap();
+        let targets = |s: &str| -> Vec<String> {
+            let selector: TierSelector = s.parse().unwrap();
+            table.targets(&selector).map(|s| s.to_string()).collect()
+        };
+        let tier1 = targets("1");
+        assert!(tier1.iter().any(|t| t == "x86_64-unknown-linux-gnu"));
+        assert!(tier1.iter().any(|t| t == "aarch64-apple-darwin"));
+        assert_eq!(tier1, targets("1-with-host-tools"));
+        let tier2 = targets("2");
+        let tier2_host = targets("2-with-host-tools");
+        assert!(tier2.iter().any(|t| t == "wasm32-unknown-unknown"));
+        assert!(!tier2_host.iter().any(|t| t == "wasm32-unknown-unknown"));
+        assert!(tier2_host
+            .iter()
+            .any(|t| t == "powerpc64le-unknown-linux-gnu"));
+        let tier3 = targets("3");
+        assert!(tier1.len() < tier2_host.len());
+        assert!(tier2_host.len() < tier2.len());
+        assert!(tier2.len() < tier3.len());
+        assert!(tier2.iter().all(|t| tier3.contains(t)));
+    }
+
+    #[test]
+    fn test_tier_table_override() {
+        let table = TierTable::parse(
+            r#"
+[targets]
+"x86_64-unknown-linux-gnu" = { tier = 1, host-tools = true, std = true }
+"x86_64-acme-none" = { tier = 3 }
+"#,
+        )
+        .unwrap();
+        let selector: TierSelector = "3".parse().unwrap();
+        assert_eq!(table.targets(&selector).count(), 2);
+        let mut buf = Vec::new();
+        table.print(&mut buf).unwrap();
+        let buf = String::from_utf8(buf).unwrap();
+        let row = buf
+            .lines()
+            .find(|l| l.starts_with("x86_64-acme-none"))
+            .unwrap();
+        assert_eq!(
+            row.split_whitespace().collect::<Vec<_>>(),
+            ["x86_64-acme-none", "3", "?", "?"]
+        );
+        assert!(TierTable::parse("[targets]\nfoo = { tier = 4 }").is_err());
+        assert!("4".parse::<TierSelector>().is_err());
+        assert!("2-with-host-toolz".parse::<TierSelector>().is_err());
     }
 }
@@ -0,0 +1,384 @@
+use crate::{new_metadata_cmd, Args, CargoChecksums, CARGO_CHECKSUM, CARGO_TOML};
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_lock::{Lockfile, SourceId};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// The name of the lockfile next to the workspace manifest
+const CARGO_LOCK: &str = "Cargo.lock";
+
+/// The part of a vendored crate's manifest identifying it.
+#[derive(Debug, Deserialize)]
+struct VendoredManifest {
+    package: VendoredManifestPackage,
+}
+
+#[derive(Debug, Deserialize)]
+struct VendoredManifestPackage {
+    name: String,
+    version: String,
+}
+
+/// A directory created by `cargo vendor`, identified by its manifest and
+/// matched against the lockfiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VendoredPackage {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    /// The source of the package, as recorded in the lockfile
+    pub(crate) source: String,
+    /// Whether the package comes from a git repository
+    pub(crate) is_git: bool,
+    /// The package checksum in `.cargo-checksum.json`
+    checksum: Option<String>,
+    /// The package checksum in the lockfile
+    locked_checksum: Option<String>,
+}
+
+/// Load the lockfiles of the manifest and all manifests to sync, which
+/// `cargo vendor` will have created if they did not exist yet.
+pub(crate) fn load_lockfiles(args: &Args) -> Result<Vec<(Utf8PathBuf, Lockfile)>> {
+    let mut r: Vec<(Utf8PathBuf, Lockfile)> = Vec::new();
+    for manifest_path in args.get_all_manifest_paths() {
+        let mut command = new_metadata_cmd(manifest_path, args.offline);
+        command.no_deps();
+        let meta = command.exec().context("Executing cargo metadata")?;
+        let path = meta.workspace_root.join(CARGO_LOCK);
+        // Members of the same workspace share a lockfile
+        if r.iter().any(|(p, _)| *p == path) {
+            continue;
+        }
+        let lockfile = Lockfile::load(&path).with_context(|| format!("Parsing {path}"))?;
+        r.push((path, lockfile));
+    }
+    Ok(r)
+}
+
+/// Identify each directory created by `cargo vendor` from the `[package]` of its
+/// manifest and the package checksum in its `.cargo-checksum.json`, and find the
+/// matching package in the lockfiles.  Returns an error listing all directories
+/// which match no locked package.
+pub(crate) fn identify_vendored_dirs(
+    output_dir: &Utf8Path,
+    lockfiles: &[(Utf8PathBuf, Lockfile)],
+) -> Result<BTreeMap<String, VendoredPackage>> {
+    let mut r = BTreeMap::new();
+    let mut unmapped = Vec::new();
+    for entry in output_dir.read_dir_utf8()? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let dir = entry.file_name();
+        match identify_vendored_dir(entry.path(), lockfiles)
+            .with_context(|| format!("Identifying vendored directory {dir}"))?
+        {
+            Some(pkg) => {
+                r.insert(dir.to_string(), pkg);
+            }
+            None => unmapped.push(dir.to_string()),
+        }
+    }
+    if !unmapped.is_empty() {
+        unmapped.sort();
+        anyhow::bail!(
+            "Failed to match vendored directories to packages in {CARGO_LOCK}: {}",
+            unmapped.join(", ")
+        );
+    }
+    Ok(r)
+}
+
+/// Identify a single vendored directory; see [`identify_vendored_dirs`].
+fn identify_vendored_dir(
+    path: &Utf8Path,
+    lockfiles: &[(Utf8PathBuf, Lockfile)],
+) -> Result<Option<VendoredPackage>> {
+    let manifest_path = path.join(CARGO_TOML);
+    let manifest = std::fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Reading {manifest_path}"))?;
+    let manifest: VendoredManifest =
+        toml::from_str(&manifest).with_context(|| format!("Parsing {manifest_path}"))?;
+    let checksums_path = path.join(CARGO_CHECKSUM);
+    let checksums = std::fs::read_to_string(&checksums_path)
+        .with_context(|| format!("Reading {checksums_path}"))?;
+    let checksums: CargoChecksums =
+        serde_json::from_str(&checksums).with_context(|| format!("Parsing {checksums_path}"))?;
+    let VendoredManifestPackage { name, version } = manifest.package;
+    let candidates: Vec<_> = locked_packages(lockfiles)
+        .filter(|(p, _)| p.name.as_str() == name && p.version.to_string() == version)
+        .collect();
+    // Prefer the package with the same checksum, if the name and version are ambiguous;
+    // a mismatch for a kept package is reported by `check_vendored`.
+    let pkg = candidates
+        .iter()
+        .find(|(p, _)| p.checksum.as_ref().map(|c| c.to_string()) == checksums.package)
+        .or(candidates.first());
+    Ok(pkg.map(|(p, source)| VendoredPackage {
+        name,
+        version,
+        source: source.to_string(),
+        is_git: source.is_git(),
+        checksum: checksums.package,
+        locked_checksum: p.checksum.as_ref().map(|c| c.to_string()),
+    }))
+}
+
+/// The packages in the lockfiles which `cargo vendor` vendors, i.e. all but path
+/// dependencies, along with their source.
+fn locked_packages(
+    lockfiles: &[(Utf8PathBuf, Lockfile)],
+) -> impl Iterator<Item = (&cargo_lock::Package, &SourceId)> {
+    lockfiles
+        .iter()
+        .flat_map(|(_, lockfile)| lockfile.packages.iter())
+        .filter_map(|p| p.source.as_ref().map(|s| (p, s)))
+        .filter(|(_, s)| !s.is_path())
+}
+
+/// Check that the lockfiles (of the manifest and the ones to sync) do not
+/// pin different checksums for the same package.
+pub(crate) fn check_conflicting_checksums(lockfiles: &[(Utf8PathBuf, Lockfile)]) -> Result<()> {
+    let mut checksums: BTreeMap<_, BTreeMap<String, Vec<&Utf8Path>>> = BTreeMap::new();
+    for (path, lockfile) in lockfiles {
+        for p in lockfile.packages.iter() {
+            let (Some(source), Some(checksum)) = (p.source.as_ref(), p.checksum.as_ref()) else {
+                continue;
+            };
+            let key = (p.name.as_str(), p.version.to_string(), source.to_string());
+            let paths = checksums.entry(key).or_default();
+            paths.entry(checksum.to_string()).or_default().push(path);
+        }
+    }
+    let conflicts: Vec<_> = checksums
+        .into_iter()
+        .filter(|(_, by_checksum)| by_checksum.len() > 1)
+        .map(|((name, version, _), by_checksum)| {
+            let pins: Vec<_> = by_checksum
+                .into_iter()
+                .map(|(checksum, paths)| format!("{checksum} in {}", join(&paths)))
+                .collect();
+            format!("{name} {version}: {}", pins.join(", "))
+        })
+        .collect();
+    if !conflicts.is_empty() {
+        anyhow::bail!(
+            "Conflicting checksums in {CARGO_LOCK} files:\n  {}",
+            conflicts.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Check the vendored directories against the lockfiles: the package checksum of
+/// each kept directory (as determined by `is_kept`) must match the locked one, and
+/// every locked package must have been vendored.  Returns the number of kept and
+/// stubbed packages.
+pub(crate) fn check_vendored(
+    lockfiles: &[(Utf8PathBuf, Lockfile)],
+    vendored: &BTreeMap<String, VendoredPackage>,
+    is_kept: impl Fn(&str) -> bool,
+) -> Result<(usize, usize)> {
+    let mut errors = Vec::new();
+    let mut kept = 0;
+    for (dir, pkg) in vendored.iter() {
+        if !is_kept(dir) {
+            continue;
+        }
+        kept += 1;
+        if let Some(locked) = pkg.locked_checksum.as_deref() {
+            if pkg.checksum.as_deref() != Some(locked) {
+                errors.push(format!(
+                    "Checksum mismatch for {} {} in {dir}: {locked} in {CARGO_LOCK}, {} in {CARGO_CHECKSUM}",
+                    pkg.name,
+                    pkg.version,
+                    pkg.checksum.as_deref().unwrap_or("none")
+                ));
+            }
+        }
+    }
+    let vendored_packages: HashSet<_> = vendored
+        .values()
+        .map(|p| (p.name.as_str(), p.version.as_str(), p.source.as_str()))
+        .collect();
+    let mut missing = BTreeSet::new();
+    for (p, source) in locked_packages(lockfiles) {
+        let (version, source) = (p.version.to_string(), source.to_string());
+        if !vendored_packages.contains(&(p.name.as_str(), version.as_str(), source.as_str())) {
+            missing.insert(format!("{} {version} ({source})", p.name));
+        }
+    }
+    if !missing.is_empty() {
+        errors.push(format!(
+            "Packages in {CARGO_LOCK} neither kept nor replaced with a stub: {}",
+            join(&missing)
+        ));
+    }
+    if !errors.is_empty() {
+        anyhow::bail!(errors.join("\n"));
+    }
+    Ok((kept, vendored.len() - kept))
+}
+
+fn join(items: impl IntoIterator<Item = impl std::fmt::Display>) -> String {
+    items
+        .into_iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCKFILE: &str = r#"
+version = 3
+
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = ["hex 0.4.3", "hex 0.3.99"]
+
+[[package]]
+name = "hex"
+version = "0.3.99"
+source = "git+https://github.com/example/hex#0123456789abcdef0123456789abcdef01234567"
+
+[[package]]
+name = "hex"
+version = "0.4.3"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "7f24254aa9a54b5c858eaee2f5bccdb46aaf0e486a595ed5fd8f86ba55232a70"
+"#;
+
+    fn write_vendored(dir: &Utf8Path, name: &str, version: &str, checksum: Option<&str>) {
+        std::fs::create_dir_all(dir).unwrap();
+        let manifest = format!("[package]\nname = \"{name}\"\nversion = \"{version}\"\n");
+        std::fs::write(dir.join(CARGO_TOML), manifest).unwrap();
+        let checksums = serde_json::json!({ "files": {}, "package": checksum });
+        std::fs::write(dir.join(CARGO_CHECKSUM), checksums.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_identify_vendored_dirs() {
+        let td = tempfile::tempdir().unwrap();
+        let vendor: &Utf8Path = td.path().try_into().unwrap();
+        let lockfiles = vec![("Cargo.lock".into(), LOCKFILE.parse().unwrap())];
+        let checksum = "7f24254aa9a54b5c858eaee2f5bccdb46aaf0e486a595ed5fd8f86ba55232a70";
+        // The directory names are deliberately not the ones cargo would use
+        write_vendored(&vendor.join("a"), "hex", "0.4.3", Some(checksum));
+        write_vendored(&vendor.join("b"), "hex", "0.3.99", None);
+        let mut r = identify_vendored_dirs(vendor, &lockfiles).unwrap();
+        assert_eq!(r["a"].name, "hex");
+        assert_eq!(r["a"].version, "0.4.3");
+        assert!(!r["a"].is_git);
+        assert_eq!(r["b"].version, "0.3.99");
+        assert!(r["b"].is_git);
+        assert!(r["b"]
+            .source
+            .starts_with("git+https://github.com/example/hex"));
+
+        let (kept, stubbed) = check_vendored(&lockfiles, &r, |dir| dir == "a").unwrap();
+        assert_eq!((kept, stubbed), (1, 1));
+
+        // A locked package which was not vendored
+        let (_, b) = r.pop_last().unwrap();
+        let e = check_vendored(&lockfiles, &r, |_| true).unwrap_err();
+        assert!(e.to_string().contains("hex 0.3.99 (git+"), "{e}");
+        r.insert("b".into(), b);
+
+        // A checksum mismatch is only an error for kept packages
+        r.get_mut("a").unwrap().checksum = Some("0".repeat(64));
+        assert!(check_vendored(&lockfiles, &r, |dir| dir == "b").is_ok());
+        let e = check_vendored(&lockfiles, &r, |dir| dir == "a").unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with("Checksum mismatch for hex 0.4.3 in a"),
+            "{e}"
+        );
+
+        // An unknown version and a path dependency are not mapped
+        write_vendored(&vendor.join("c"), "hex", "0.4.2", Some(checksum));
+        write_vendored(&vendor.join("d"), "foo", "0.1.0", None);
+        let e = identify_vendored_dirs(vendor, &lockfiles).unwrap_err();
+        assert!(e.to_string().ends_with(": c, d"), "{e}");
+    }
+
+    #[test]
+    fn test_identify_vendored_git_dirs() {
+        let td = tempfile::tempdir().unwrap();
+        let vendor: &Utf8Path = td.path().try_into().unwrap();
+        let crates_io = "registry+https://github.com/rust-lang/crates.io-index";
+        let git = "git+https://github.com/KokaKiwi/rust-hex?rev=b2b4370#b2b4370";
+        let checksum = "7f24254aa9a54b5c858eaee2f5bccdb46aaf0e486a595ed5fd8f86ba55232a70";
+        let lockfile = format!(
+            r#"
+version = 3
+
+[[package]]
+name = "hex"
+version = "0.5.0"
+source = "{git}"
+
+[[package]]
+name = "hex"
+version = "0.4.3"
+source = "{crates_io}"
+checksum = "{checksum}"
+
+[[package]]
+name = "memchr"
+version = "2.7.4"
+source = "{git}"
+"#
+        );
+        let lockfiles = vec![("Cargo.lock".into(), lockfile.parse().unwrap())];
+        // The newest version (from git) gets the unversioned directory
+        write_vendored(&vendor.join("hex"), "hex", "0.5.0", None);
+        write_vendored(&vendor.join("hex-0.4.3"), "hex", "0.4.3", Some(checksum));
+        write_vendored(&vendor.join("memchr-2.7.4"), "memchr", "2.7.4", None);
+        let r = identify_vendored_dirs(vendor, &lockfiles).unwrap();
+        let sources: Vec<_> = r
+            .iter()
+            .map(|(dir, p)| {
+                (
+                    dir.as_str(),
+                    p.version.as_str(),
+                    p.source.as_str(),
+                    p.is_git,
+                )
+            })
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                ("hex", "0.5.0", git, true),
+                ("hex-0.4.3", "0.4.3", crates_io, false),
+                ("memchr-2.7.4", "2.7.4", git, true),
+            ]
+        );
+        let (kept, stubbed) = check_vendored(&lockfiles, &r, |dir| dir != "memchr-2.7.4").unwrap();
+        assert_eq!((kept, stubbed), (2, 1));
+    }
+
+    #[test]
+    fn test_check_conflicting_checksums() {
+        let lockfile: Lockfile = LOCKFILE.parse().unwrap();
+        let mut other = lockfile.clone();
+        let mut lockfiles = vec![("a/Cargo.lock".into(), lockfile)];
+        lockfiles.push(("b/Cargo.lock".into(), other.clone()));
+        assert!(check_conflicting_checksums(&lockfiles).is_ok());
+        let checksum = "0".repeat(64);
+        other.packages[2].checksum = Some(checksum.parse().unwrap());
+        lockfiles.push(("c/Cargo.lock".into(), other));
+        let e = check_conflicting_checksums(&lockfiles).unwrap_err();
+        assert!(
+            e.to_string().contains(&format!(
+                "hex 0.4.3: {checksum} in c/Cargo.lock, 7f24254aa9a54b5c858eaee2f5bccdb46aaf0e486a595ed5fd8f86ba55232a70 in a/Cargo.lock, b/Cargo.lock"
+            )),
+            "{e}"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use super::common::{git_commit_all, tempdir, vendor, write_file_create_parents, VendorOptions};
+
+#[test]
+#[serial_test::parallel]
+fn strip_binaries() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let repo = test_folder.join("repo");
+    for name in ["blobs", "linked"] {
+        write_file_create_parents(
+            &repo,
+            &format!("{name}/Cargo.toml"),
+            &format!(
+                r#"
+        [package]
+        name = "{name}"
+        version = "0.1.0"
+    "#
+            ),
+        )
+        .unwrap();
+        write_file_create_parents(&repo, &format!("{name}/src/lib.rs"), "").unwrap();
+        write_file_create_parents(&repo, &format!("{name}/lib/libfoo.a"), "!<arch>\n").unwrap();
+    }
+    write_file_create_parents(&repo, "blobs/prebuilt/foo.so", "\x7fELF\x02\x01\x01").unwrap();
+    // Only the contents matter, not the extension
+    write_file_create_parents(&repo, "blobs/prebuilt/notes.o", "text\n").unwrap();
+    write_file_create_parents(
+        &repo,
+        "linked/build.rs",
+        "fn main() { println!(\"cargo:rustc-link-lib=static=foo\"); let _ = \"lib/libfoo.a\"; }\n",
+    )
+    .unwrap();
+    git_commit_all(&repo);
+
+    let vendor_deps = |project: &str, deps: &str, strip_binaries: &str| {
+        let project = test_folder.join(project);
+        let manifest = write_file_create_parents(
+            &project,
+            "Cargo.toml",
+            &format!(
+                r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        {deps}
+
+        [package.metadata.vendor-filter]
+        strip-binaries = "{strip_binaries}"
+    "#
+            ),
+        )
+        .unwrap();
+        write_file_create_parents(&project, "src/lib.rs", "").unwrap();
+        let output_folder = project.join("vendor");
+        let output = vendor(VendorOptions {
+            output: Some(&output_folder),
+            manifest_path: Some(&manifest),
+            ..Default::default()
+        })
+        .unwrap();
+        (output_folder, output)
+    };
+
+    let (output_folder, output) = vendor_deps(
+        "a",
+        &format!(r#"blobs = {{ git = "file://{repo}" }}"#),
+        "remove",
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    assert!(!output_folder.join("blobs/lib/libfoo.a").exists());
+    assert!(!output_folder.join("blobs/prebuilt/foo.so").exists());
+    assert!(output_folder.join("blobs/prebuilt/notes.o").exists());
+    assert!(
+        stderr.contains("Removed binary from crate blobs: prebuilt/foo.so (ELF)"),
+        "{stderr}"
+    );
+    let checksums =
+        std::fs::read_to_string(output_folder.join("blobs/.cargo-checksum.json")).unwrap();
+    assert!(!checksums.contains("libfoo.a"), "{checksums}");
+    assert!(checksums.contains("notes.o"), "{checksums}");
+
+    let deps = format!(r#"linked = {{ git = "file://{repo}" }}"#);
+    let (output_folder, output) = vendor_deps("b", &deps, "report");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    assert!(output_folder.join("linked/lib/libfoo.a").exists());
+    assert!(
+        stderr.contains("  linked: lib/libfoo.a (ar archive, referenced by build.rs)"),
+        "{stderr}"
+    );
+
+    // Removing a binary used by the build script is an error
+    let (_, output) = vendor_deps("c", &deps, "remove");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("referenced by build scripts: linked/lib/libfoo.a (build.rs)"),
+        "{stderr}"
+    );
+}
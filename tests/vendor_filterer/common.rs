@@ -65,8 +65,20 @@ impl fmt::Display for VendorFormat {
 pub(crate) struct VendorOptions<'a, 'b, 'c, 'd, 'e, 'f> {
     pub output: Option<&'a Utf8Path>,
     pub platforms: Option<&'b [&'b str]>,
+    pub exclude_platforms: Option<&'b [&'b str]>,
+    pub platform_cfg: Option<&'b str>,
+    pub host_platforms: Option<&'b [&'b str]>,
     pub tier: Option<&'static str>,
+    pub tier_table: Option<&'a Utf8Path>,
     pub exclude_crate_paths: Option<&'c [&'c str]>,
+    pub keep_crate_paths: Option<&'c [&'c str]>,
+    pub strict_excludes: bool,
+    pub exclude_presets: Option<&'c [&'c str]>,
+    pub exclude_crates: Option<&'c [&'c str]>,
+    pub keep_crates: Option<&'c [&'c str]>,
+    pub keep_crates_dependencies: bool,
+    pub packages: Option<&'c [&'c str]>,
+    pub exclude_packages: Option<&'c [&'c str]>,
     pub format: Option<VendorFormat>,
     pub manifest_path: Option<&'d Utf8Path>,
     pub sync: Vec<&'e Utf8Path>,
@@ -90,9 +102,29 @@ pub(crate) fn vendor(options: VendorOptions) -> Result<Output> {
     if let Some(platforms) = options.platforms {
         cmd.args(platforms.iter().map(|&p| format!("--platform={p}")));
     }
+    if let Some(exclude_platforms) = options.exclude_platforms {
+        cmd.args(
+            exclude_platforms
+                .iter()
+                .map(|&p| format!("--exclude-platform={p}")),
+        );
+    }
+    if let Some(platform_cfg) = options.platform_cfg {
+        cmd.arg(format!("--platform-cfg={platform_cfg}"));
+    }
+    if let Some(host_platforms) = options.host_platforms {
+        cmd.args(
+            host_platforms
+                .iter()
+                .map(|&p| format!("--host-platform={p}")),
+        );
+    }
     if let Some(tier) = options.tier {
         cmd.args(["--tier", tier]);
     }
+    if let Some(tier_table) = options.tier_table {
+        cmd.arg(format!("--tier-table={tier_table}"));
+    }
     if let Some(exclude_crate_paths) = options.exclude_crate_paths {
         cmd.args(
             exclude_crate_paths
@@ -100,6 +132,42 @@ pub(crate) fn vendor(options: VendorOptions) -> Result<Output> {
                 .map(|&p| format!("--exclude-crate-path={p}")),
         );
     }
+    if let Some(keep_crate_paths) = options.keep_crate_paths {
+        cmd.args(
+            keep_crate_paths
+                .iter()
+                .map(|&p| format!("--keep-crate-path={p}")),
+        );
+    }
+    if options.strict_excludes {
+        cmd.arg("--strict-excludes");
+    }
+    if let Some(exclude_presets) = options.exclude_presets {
+        cmd.args(
+            exclude_presets
+                .iter()
+                .map(|&p| format!("--exclude-preset={p}")),
+        );
+    }
+    if let Some(exclude_crates) = options.exclude_crates {
+        cmd.args(
+            exclude_crates
+                .iter()
+                .map(|&p| format!("--exclude-crate={p}")),
+        );
+    }
+    if let Some(keep_crates) = options.keep_crates {
+        cmd.args(keep_crates.iter().map(|&p| format!("--keep-crate={p}")));
+    }
+    if let Some(packages) = options.packages {
+        cmd.args(packages.iter().map(|&p| format!("--package={p}")));
+    }
+    if let Some(exclude_packages) = options.exclude_packages {
+        cmd.args(exclude_packages.iter().map(|&p| format!("--exclude={p}")));
+    }
+    if options.keep_crates_dependencies {
+        cmd.arg("--keep-crates-dependencies");
+    }
     if let Some(format) = options.format {
         cmd.arg(format!("--format={format}"));
     }
@@ -156,6 +224,22 @@ pub(crate) fn write_file_create_parents(
     Ok(path)
 }
 
+/// Initialize a git repository in the directory and commit its contents.
+pub(crate) fn git_commit_all(dir: &Utf8Path) {
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "init"]);
+}
+
 pub(crate) fn verify_no_windows(dir: &Utf8Path) {
     let mut windows_lib = dir.join("windows-sys/src/lib.rs");
     assert!(windows_lib.exists());
@@ -176,6 +260,19 @@ pub(crate) fn verify_no_macos(dir: &Utf8Path) {
     assert_eq!(macos_lib.read_dir_utf8().unwrap().count(), 1);
 }
 
+pub(crate) fn verify_crate_is_stub(output_folder: &Utf8Path, name: &str) {
+    let crate_lib = output_folder.join(name).join("src/lib.rs");
+    assert!(
+        crate_lib.exists(),
+        "Package has no src/lib.rs-file in the vendor dir"
+    );
+    assert_eq!(
+        crate_lib.metadata().unwrap().len(),
+        0,
+        "Package {name} was not replaced with a stub"
+    );
+}
+
 pub(crate) fn verify_crate_is_no_stub(output_folder: &Utf8Path, name: &str) {
     let crate_dir = output_folder.join(name);
     assert!(
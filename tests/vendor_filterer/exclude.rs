@@ -1,4 +1,7 @@
-use super::common::{tempdir, vendor, verify_no_windows, VendorOptions};
+use super::common::{
+    tempdir, vendor, verify_crate_is_no_stub, verify_crate_is_stub, verify_no_windows,
+    write_file_create_parents, VendorOptions,
+};
 
 #[test]
 #[serial_test::parallel]
@@ -39,6 +42,28 @@ fn windows_with_dep_kind_filter_normal() {
     assert!(!test_folder.exists());
 }
 
+#[test]
+#[serial_test::parallel]
+fn multiple_platforms_with_dep_kind_filter_normal() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu", "x86_64-pc-windows-msvc"]),
+        keep_dep_kinds: Some("normal"),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    // Linux-only dependencies must survive the Windows pass...
+    verify_crate_is_no_stub(&test_folder, "rustix");
+    verify_crate_is_no_stub(&test_folder, "linux-raw-sys");
+    // ...and the Windows-only ones are kept too
+    verify_crate_is_no_stub(&test_folder, "windows-sys-0.59.0");
+    // Development dependencies are still stubbed out
+    assert!(!test_folder.join("serial_test/tests").exists());
+}
+
 #[test]
 #[serial_test::parallel]
 fn exclude_with_glob_patterns() {
@@ -69,3 +94,196 @@ fn exclude_with_glob_patterns() {
         }
     }
 }
+
+#[test]
+#[serial_test::parallel]
+fn keep_crate_paths() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu"]),
+        exclude_crate_paths: Some(&["hex#src/*.rs", "hex#!src/lib.rs"]),
+        keep_crate_paths: Some(&["hex#src", "hex#LICENSE-*"]),
+        ..Default::default()
+    })
+    .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    let hex_dir = test_folder.join("hex");
+    for kept in ["Cargo.toml", "LICENSE-MIT", "src/lib.rs"] {
+        assert!(hex_dir.join(kept).exists(), "{kept}");
+    }
+    for removed in ["README.md", "benches", "tests", "src/error.rs"] {
+        assert!(!hex_dir.join(removed).exists(), "{removed}");
+    }
+    assert!(stderr.contains("Filtered crate hex: removed "), "{stderr}");
+    verify_crate_is_no_stub(&test_folder, "rustix");
+}
+
+#[test]
+#[serial_test::parallel]
+fn unmatched_excludes() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let exclude_crate_paths = &["*#tests", "*#nonexistent", "hex#benchs"];
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu"]),
+        exclude_crate_paths: Some(exclude_crate_paths),
+        ..Default::default()
+    })
+    .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    assert!(!stderr.contains("No match"), "{stderr}");
+    let summary: Vec<_> = stderr
+        .lines()
+        .skip_while(|l| *l != "Warning: Crate path patterns matching nothing:")
+        .skip(1)
+        .take_while(|l| l.starts_with("  "))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            "  exclude-crate-paths: *#nonexistent",
+            "  exclude-crate-paths: hex#benchs"
+        ],
+        "{stderr}"
+    );
+
+    // Only the pattern for a specific crate is an error in strict mode
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu"]),
+        exclude_crate_paths: Some(exclude_crate_paths),
+        strict_excludes: true,
+        ..Default::default()
+    })
+    .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("matched nothing (strict-excludes): hex#benchs\n"),
+        "{stderr}"
+    );
+}
+
+#[test]
+#[serial_test::parallel]
+fn strict_excludes_with_cargo_toml() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = "0.4"
+
+        [package.metadata.vendor-filter]
+        exclude-crate-paths = [ { name = "hex", exclude = "benchs" } ]
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    // The flag applies to the excludes from Cargo.toml instead of replacing them
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder.join("vendor")),
+        manifest_path: Some(&manifest),
+        strict_excludes: true,
+        ..Default::default()
+    })
+    .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("matched nothing (strict-excludes): hex#benchs\n"),
+        "{stderr}"
+    );
+}
+
+#[test]
+#[serial_test::parallel]
+fn exclude_presets() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu"]),
+        exclude_presets: Some(&["tests", "benches", "docs"]),
+        ..Default::default()
+    })
+    .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    assert!(!test_folder.join("hex/tests").exists());
+    assert!(!test_folder.join("anyhow/tests/common").exists());
+    // Targets listed in the manifests are kept
+    assert!(test_folder.join("hex/benches/hex.rs").exists());
+    assert!(test_folder.join("anyhow/tests/test_macros.rs").exists());
+    assert!(test_folder.join("hex/README.md").exists());
+}
+
+#[test]
+#[serial_test::parallel]
+fn exclude_crates() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu"]),
+        exclude_crates: Some(&["hex", "linux-raw-*"]),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_stub(&test_folder, "hex");
+    verify_crate_is_stub(&test_folder, "linux-raw-sys");
+    verify_crate_is_no_stub(&test_folder, "rustix");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Excluded crates: "), "{stderr}");
+}
+
+#[test]
+#[serial_test::parallel]
+fn exclude_crates_unmatched() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu"]),
+        exclude_crates: Some(&["hex", "openssl-scr"]),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_stub(&test_folder, "hex");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Warning: No match for excluded crate: openssl-scr"),
+        "{stderr}"
+    );
+
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu"]),
+        exclude_crates: Some(&["hex", "openssl-scr"]),
+        strict_excludes: true,
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("exclude-crates patterns matched nothing (strict-excludes): openssl-scr"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,93 @@
+use super::common::{
+    git_commit_all, tempdir, vendor, verify_crate_is_no_stub, verify_crate_is_stub,
+    write_file_create_parents, VendorOptions,
+};
+use camino::Utf8Path;
+
+/// Create a git repository with a `hex` crate (older than the one on crates.io),
+/// and two crates only available from git.
+fn create_git_repo(dir: &Utf8Path) {
+    for (name, version) in [
+        ("hex", "0.3.99"),
+        ("gitkept", "0.1.0"),
+        ("gitonly", "0.1.0"),
+    ] {
+        write_file_create_parents(
+            dir,
+            &format!("{name}/Cargo.toml"),
+            &format!(
+                r#"
+        [package]
+        name = "{name}"
+        version = "{version}"
+    "#
+            ),
+        )
+        .unwrap();
+        write_file_create_parents(dir, &format!("{name}/src/lib.rs"), "pub fn f() {}\n").unwrap();
+        write_file_create_parents(dir, &format!("{name}/extra/data.txt"), "data\n").unwrap();
+    }
+    git_commit_all(dir);
+}
+
+#[test]
+#[serial_test::parallel]
+fn git_sources() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let repo = test_folder.join("repo");
+    create_git_repo(&repo);
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "foo/Cargo.toml",
+        &format!(
+            r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = "0.4"
+        hex-git = {{ package = "hex", git = "file://{repo}" }}
+        gitkept = {{ git = "file://{repo}" }}
+
+        [target.'cfg(windows)'.dependencies]
+        gitonly = {{ git = "file://{repo}" }}
+
+        [package.metadata.vendor-filter]
+        platforms = ["x86_64-unknown-linux-gnu"]
+        exclude-crate-paths = [ {{ name = "hex", exclude = "extra" }}, {{ name = "gitkept", exclude = "extra" }} ]
+    "#
+        ),
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "foo/src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+
+    // The newest version gets the unversioned directory
+    verify_crate_is_no_stub(&output_folder, "hex");
+    verify_crate_is_no_stub(&output_folder, "hex-0.3.99");
+    verify_crate_is_no_stub(&output_folder, "gitkept");
+    verify_crate_is_stub(&output_folder, "gitonly");
+    // Excludes apply to git sources and versioned directories
+    assert!(!output_folder.join("hex-0.3.99/extra").exists());
+    assert!(!output_folder.join("gitkept/extra").exists());
+
+    let summary = stderr
+        .lines()
+        .skip_while(|l| *l != "Git sources:")
+        .nth(1)
+        .unwrap_or_else(|| panic!("Missing git sources: {stderr}"));
+    assert!(summary.contains(&format!("git+file://{repo}")), "{summary}");
+    assert!(
+        summary.ends_with("gitkept 0.1.0, gitonly 0.1.0 (stub), hex 0.3.99"),
+        "{summary}"
+    );
+}
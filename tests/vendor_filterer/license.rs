@@ -0,0 +1,178 @@
+use super::common::{
+    tempdir, vendor, verify_crate_is_no_stub, verify_crate_is_stub, write_file_create_parents,
+    VendorOptions,
+};
+
+#[test]
+#[serial_test::parallel]
+fn license_policy_fail() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = "0.4"
+        memchr = "2"
+
+        [package.metadata.vendor-filter]
+        allowed-licenses = ["Apache-2.0"]
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("License policy violated by 1 crate(s)"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("foo 0.1.0 -> memchr"), "{stderr}");
+}
+
+#[test]
+#[serial_test::parallel]
+fn license_policy_stub_optional() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = "0.4"
+        memchr = { version = "2", optional = true }
+
+        [package.metadata.vendor-filter]
+        all-features = true
+        allowed-licenses = ["Apache-2.0"]
+        license-policy = "stub"
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_no_stub(&output_folder, "hex");
+    verify_crate_is_stub(&output_folder, "memchr");
+}
+
+#[test]
+#[serial_test::parallel]
+fn license_policy_stub_optional_after_filtering() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = "0.4"
+        memchr = { version = "2", optional = true }
+
+        [target.'cfg(windows)'.dependencies]
+        memchr = "2"
+
+        [dev-dependencies]
+        memchr = "2"
+
+        [package.metadata.vendor-filter]
+        platforms = ["x86_64-unknown-linux-gnu"]
+        keep-dep-kinds = "no-dev"
+        all-features = true
+        allowed-licenses = ["Apache-2.0"]
+        license-policy = "stub"
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    // memchr is only required on Windows and by the tests, which are filtered out
+    assert!(output.status.success(), "{output:?}");
+    verify_crate_is_no_stub(&output_folder, "hex");
+    verify_crate_is_stub(&output_folder, "memchr");
+}
+
+#[test]
+#[serial_test::parallel]
+fn license_policy_path_after_filtering() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        bar = { path = "bar" }
+
+        [dev-dependencies]
+        memchr = "2"
+
+        [package.metadata.vendor-filter]
+        keep-dep-kinds = "no-dev"
+        allowed-licenses = ["Apache-2.0"]
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    write_file_create_parents(
+        &test_folder,
+        "bar/Cargo.toml",
+        r#"
+        [package]
+        name = "bar"
+        version = "0.1.0"
+
+        [target.'cfg(unix)'.dependencies]
+        memchr = "2"
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "bar/src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // The dev-dependency is filtered out, so it does not explain why memchr is kept
+    assert!(
+        stderr.contains("foo 0.1.0 -> bar 0.1.0 -> memchr"),
+        "{stderr}"
+    );
+}
@@ -1,7 +1,10 @@
 mod basic;
+mod binaries;
 pub mod common;
 mod exclude;
 mod format;
+mod git;
+mod license;
 mod platform;
 mod sync;
 mod toml;
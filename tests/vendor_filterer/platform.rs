@@ -1,4 +1,7 @@
-use super::common::{tempdir, vendor, verify_no_windows, VendorOptions};
+use super::common::{
+    tempdir, vendor, verify_crate_is_no_stub, verify_crate_is_stub, verify_no_windows,
+    write_file_create_parents, VendorOptions,
+};
 
 #[test]
 fn linux() {
@@ -42,3 +45,394 @@ fn linux_glob() {
     assert!(output.status.success());
     verify_no_windows(&test_folder);
 }
+
+#[test]
+#[serial_test::parallel]
+fn unmatched_platform() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    for platform in ["*-unknown-linux-gnuabc", "x86_64-unknown-linux-gnuabc"] {
+        let output = vendor(VendorOptions {
+            output: Some(&test_folder),
+            platforms: Some(&[platform]),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(platform), "{stderr}");
+        assert!(!test_folder.exists());
+    }
+}
+
+#[test]
+#[serial_test::parallel]
+fn linux_exclude_platforms() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["*-unknown-linux-*", "host"]),
+        exclude_platforms: Some(&["*-linux-musl*", "sparc*"]),
+        tier: Some("2"),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_no_windows(&test_folder);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let platforms = stderr
+        .lines()
+        .find_map(|l| l.strip_prefix("Filtered to target platforms: "))
+        .unwrap();
+    assert!(
+        platforms.contains("\"aarch64-unknown-linux-gnu\""),
+        "{platforms}"
+    );
+    assert!(!platforms.contains("musl"), "{platforms}");
+    assert!(!platforms.contains("sparc"), "{platforms}");
+    assert!(!platforms.contains("\"host\""), "{platforms}");
+}
+
+#[test]
+#[serial_test::parallel]
+fn custom_tier_table() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let tier_table = write_file_create_parents(
+        &test_folder,
+        "tiers.toml",
+        r#"
+        [targets]
+        "x86_64-unknown-linux-gnu" = { tier = 1, host-tools = true, std = true }
+        "x86_64-pc-windows-msvc" = { tier = 2, host-tools = true, std = true }
+    "#,
+    )
+    .unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        tier: Some("1"),
+        tier_table: Some(&tier_table),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_no_windows(&output_folder);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(r#"Filtered to target platforms: ["x86_64-unknown-linux-gnu"]"#),
+        "{stderr}"
+    );
+}
+
+#[test]
+#[serial_test::parallel]
+fn linux_cfg() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platform_cfg: Some(r#"cfg(all(unix, not(target_os = "macos")))"#),
+        tier: Some("1"),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_no_windows(&test_folder);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("x86_64-unknown-linux-gnu"), "{stderr}");
+    assert!(!stderr.contains("x86_64-apple-darwin"), "{stderr}");
+}
+
+#[test]
+#[serial_test::parallel]
+fn linux_keep_crates() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu"]),
+        keep_crates: Some(&["windows-sys"]),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_no_stub(&test_folder, "windows-sys");
+    verify_crate_is_stub(&test_folder, "windows-targets");
+}
+
+#[test]
+#[serial_test::parallel]
+fn linux_keep_crates_dependencies() {
+    let (_td, mut test_folder) = tempdir().unwrap();
+    test_folder.push("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder),
+        platforms: Some(&["x86_64-unknown-linux-gnu"]),
+        keep_crates: Some(&["windows-sys"]),
+        keep_crates_dependencies: true,
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_no_stub(&test_folder, "windows-sys");
+    verify_crate_is_no_stub(&test_folder, "windows-targets");
+    verify_crate_is_no_stub(&test_folder, "windows_x86_64_msvc");
+}
+
+#[test]
+#[serial_test::parallel]
+fn custom_target_spec() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let spec = write_file_create_parents(
+        &test_folder,
+        "x86_64-acme-linux.json",
+        r#"{
+            "llvm-target": "x86_64-unknown-linux-gnu",
+            "arch": "x86_64",
+            "os": "linux",
+            "env": "gnu",
+            "target-family": ["unix"],
+            "target-pointer-width": "64"
+        }"#,
+    )
+    .unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        platforms: Some(&[spec.as_str()]),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_no_windows(&output_folder);
+    verify_crate_is_no_stub(&output_folder, "rustix");
+}
+
+#[test]
+#[serial_test::parallel]
+fn custom_target_spec_llvm_target() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let spec = write_file_create_parents(
+        &test_folder,
+        "x86_64-acme-linux.json",
+        r#"{
+            "llvm-target": "x86_64-unknown-linux-gnu",
+            "arch": "x86_64",
+            "os": "linux",
+            "env": "gnu",
+            "target-family": ["unix"],
+            "target-pointer-width": "64"
+        }"#,
+    )
+    .unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "foo/Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [target.'cfg(target_os = "linux")'.dependencies]
+        hex = "0.4"
+
+        [target.x86_64-unknown-linux-gnu.dependencies]
+        memchr = "2"
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "foo/src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        platforms: Some(&[spec.as_str()]),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    verify_crate_is_no_stub(&output_folder, "hex");
+    // Cargo matches the spec by its name only, not by its llvm-target
+    verify_crate_is_stub(&output_folder, "memchr");
+}
+
+#[test]
+#[serial_test::parallel]
+fn groups() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = { version = "0.4", optional = true }
+
+        [target.'cfg(windows)'.dependencies]
+        memchr = "2"
+
+        [build-dependencies]
+        bitflags = "1.3"
+
+        [target.'cfg(windows)'.build-dependencies]
+        cfg-if = "1"
+
+        [package.metadata.vendor-filter]
+        host-platforms = ["x86_64-unknown-linux-gnu"]
+        groups = [
+            { platforms = ["x86_64-unknown-linux-gnu"], all-features = true, keep-dep-kinds = "normal" },
+            { platforms = ["x86_64-pc-windows-msvc"], keep-dep-kinds = "no-dev" },
+        ]
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_no_stub(&output_folder, "hex");
+    verify_crate_is_no_stub(&output_folder, "memchr");
+    verify_crate_is_no_stub(&output_folder, "bitflags");
+    verify_crate_is_stub(&output_folder, "cfg-if");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("groups[1]: Filtered to dependency kinds: no-dev"),
+        "{stderr}"
+    );
+}
+
+#[test]
+#[serial_test::parallel]
+fn platform_features() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = { version = "0.4", optional = true }
+        memchr = { version = "2", optional = true }
+
+        [package.metadata.vendor-filter]
+        platforms = ["x86_64-unknown-linux-gnu"]
+
+        [package.metadata.vendor-filter.platform-features]
+        "*-linux-gnu" = ["hex"]
+        "*-windows-msvc" = ["memchr"]
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_no_stub(&output_folder, "hex");
+    verify_crate_is_stub(&output_folder, "memchr");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("No target platform matches platform-features pattern: *-windows-msvc"),
+        "{stderr}"
+    );
+}
+
+#[test]
+#[serial_test::parallel]
+fn host_platforms() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [target.'cfg(target_arch = "x86_64")'.build-dependencies]
+        hex = "0.4"
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    for (host, hex_is_stub) in [
+        ("x86_64-unknown-linux-gnu", false),
+        ("aarch64-unknown-linux-gnu", true),
+    ] {
+        let output_folder = test_folder.join(format!("vendor-{host}"));
+        let output = vendor(VendorOptions {
+            output: Some(&output_folder),
+            manifest_path: Some(&manifest),
+            platforms: Some(&["aarch64-unknown-linux-gnu"]),
+            host_platforms: Some(&[host]),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(output.status.success());
+        if hex_is_stub {
+            verify_crate_is_stub(&output_folder, "hex");
+        } else {
+            verify_crate_is_no_stub(&output_folder, "hex");
+        }
+    }
+}
+
+#[test]
+#[serial_test::parallel]
+fn platform_features_do_not_leak() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        edition = "2021"
+
+        [features]
+        unix-hex = ["dep:hex"]
+
+        [target.'cfg(unix)'.dependencies]
+        hex = { version = "0.4", optional = true }
+
+        [target.'cfg(windows)'.dependencies]
+        memchr = "2"
+
+        [package.metadata.vendor-filter]
+        platforms = ["x86_64-unknown-linux-gnu", "x86_64-pc-windows-msvc"]
+
+        [package.metadata.vendor-filter.platform-features]
+        "*-windows-msvc" = ["unix-hex"]
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    // The feature is only enabled for Windows, where the dependency is disabled
+    verify_crate_is_stub(&output_folder, "hex");
+    verify_crate_is_no_stub(&output_folder, "memchr");
+}
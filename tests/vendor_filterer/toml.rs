@@ -1,5 +1,6 @@
 use super::common::{
-    tempdir, vendor, verify_crate_is_no_stub, write_file_create_parents, VendorOptions,
+    tempdir, vendor, verify_crate_is_no_stub, verify_crate_is_stub, write_file_create_parents,
+    VendorOptions,
 };
 
 #[test]
@@ -69,3 +70,232 @@ fn metadata() {
     assert!(hex.exists());
     assert!(!hex.join("benches").exists());
 }
+
+#[test]
+#[serial_test::parallel]
+fn workspace_members_features() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [workspace]
+        members = ["a", "b"]
+        resolver = "2"
+
+        [workspace.metadata.vendor-filter]
+        members.a = { features = ["hex"] }
+    "#,
+    )
+    .unwrap();
+    for (name, dep) in [
+        ("a", "hex = { version = \"0.4\", optional = true }"),
+        ("b", "memchr = { version = \"2\", optional = true }"),
+    ] {
+        write_file_create_parents(
+            &test_folder,
+            &format!("{name}/Cargo.toml"),
+            &format!(
+                r#"
+        [package]
+        name = "{name}"
+        version = "0.1.0"
+
+        [dependencies]
+        {dep}
+    "#
+            ),
+        )
+        .unwrap();
+        write_file_create_parents(&test_folder, &format!("{name}/src/lib.rs"), "").unwrap();
+    }
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_no_stub(&output_folder, "hex");
+    verify_crate_is_stub(&output_folder, "memchr");
+}
+
+#[test]
+#[serial_test::parallel]
+fn workspace_packages() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [workspace]
+        members = ["a", "b", "c"]
+        resolver = "2"
+
+        [workspace.metadata.vendor-filter]
+        packages = ["a", "c"]
+    "#,
+    )
+    .unwrap();
+    for (name, dep) in [
+        ("a", "hex = \"0.4\""),
+        ("b", "memchr = \"2\""),
+        ("c", "bitflags = \"1.3\""),
+    ] {
+        write_file_create_parents(
+            &test_folder,
+            &format!("{name}/Cargo.toml"),
+            &format!(
+                r#"
+        [package]
+        name = "{name}"
+        version = "0.1.0"
+
+        [dependencies]
+        {dep}
+    "#
+            ),
+        )
+        .unwrap();
+        write_file_create_parents(&test_folder, &format!("{name}/src/lib.rs"), "").unwrap();
+    }
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_no_stub(&output_folder, "hex");
+    verify_crate_is_stub(&output_folder, "memchr");
+    verify_crate_is_no_stub(&output_folder, "bitflags");
+
+    // The command line overrides the configuration
+    let output_folder = test_folder.join("vendor-cli");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        packages: Some(&["*"]),
+        exclude_packages: Some(&["a"]),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_stub(&output_folder, "hex");
+    verify_crate_is_no_stub(&output_folder, "memchr");
+    verify_crate_is_no_stub(&output_folder, "bitflags");
+
+    let output = vendor(VendorOptions {
+        output: Some(&test_folder.join("vendor-unknown")),
+        manifest_path: Some(&manifest),
+        packages: Some(&["d"]),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("No workspace member found for packages"),
+        "{stderr}"
+    );
+}
+
+#[test]
+#[serial_test::parallel]
+fn feature_sets() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = { version = "0.4", optional = true }
+        memchr = { version = "2", optional = true }
+        bitflags = { version = "1.3", optional = true }
+
+        [package.metadata.vendor-filter]
+        feature-sets = [["hex"], ["hex", "memchr"]]
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_no_stub(&output_folder, "hex");
+    verify_crate_is_no_stub(&output_folder, "memchr");
+    verify_crate_is_stub(&output_folder, "bitflags");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let report = |name: &str| {
+        stderr
+            .lines()
+            .find(|l| l.starts_with(&format!("  {name} ")))
+            .unwrap_or_else(|| panic!("{stderr}"))
+            .split_once(": ")
+            .unwrap()
+            .1
+            .to_string()
+    };
+    assert_eq!(report("hex"), r#"["hex"], ["hex", "memchr"]"#);
+    assert_eq!(report("memchr"), r#"["hex", "memchr"]"#);
+}
+
+#[test]
+#[serial_test::parallel]
+fn stub_style_compile_error() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let manifest = write_file_create_parents(
+        &test_folder,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        serde = { version = "1", features = ["derive"] }
+
+        [target.'cfg(windows)'.dependencies]
+        windows-sys = "0.59"
+
+        [package.metadata.vendor-filter]
+        platforms = ["x86_64-unknown-linux-gnu"]
+        exclude-crates = ["serde_derive"]
+        stub-style = "compile-error"
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&test_folder, "src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    verify_crate_is_no_stub(&output_folder, "serde");
+
+    let librs = std::fs::read_to_string(output_folder.join("windows-sys/src/lib.rs")).unwrap();
+    assert_eq!(
+        librs.trim(),
+        r#"compile_error!("crate windows-sys was removed by cargo-vendor-filterer: not needed for platforms [\"x86_64-unknown-linux-gnu\"]");"#
+    );
+    let librs = std::fs::read_to_string(output_folder.join("serde_derive/src/lib.rs")).unwrap();
+    assert!(librs.contains("excluded by exclude-crates"), "{librs}");
+    // The stub of a procedural macro is still one
+    let manifest = std::fs::read_to_string(output_folder.join("serde_derive/Cargo.toml")).unwrap();
+    let manifest: toml::Value = toml::from_str(&manifest).unwrap();
+    assert_eq!(manifest["lib"]["proc-macro"].as_bool(), Some(true));
+}
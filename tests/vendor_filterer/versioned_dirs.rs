@@ -129,3 +129,53 @@ fn multiple_versions() {
     verify_crate_is_no_stub(&output_folder, "hex-0.4.3");
     verify_crate_is_no_stub(&output_folder, "hex-0.3.2");
 }
+
+#[test]
+#[serial_test::parallel]
+fn exclude_by_version() {
+    let (_td, test_folder) = tempdir().unwrap();
+    let dep_a = test_folder.join("A");
+    let dep_b = test_folder.join("B");
+    let manifest_a = write_file_create_parents(
+        &dep_a,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = "0.4.3"
+        bar = { path="../B/" }
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&dep_a, "src/lib.rs", "").unwrap();
+    let _manifest_b = write_file_create_parents(
+        &dep_b,
+        "Cargo.toml",
+        r#"
+        [package]
+        name = "bar"
+        version = "0.1.0"
+
+        [dependencies]
+        hex = "0.3.2"
+    "#,
+    )
+    .unwrap();
+    write_file_create_parents(&dep_b, "src/lib.rs", "").unwrap();
+    let output_folder = test_folder.join("vendor");
+    let output = vendor(VendorOptions {
+        output: Some(&output_folder),
+        manifest_path: Some(&manifest_a),
+        exclude_crate_paths: Some(&["hex@^0.4#README.md", "h?x@<0.4#Dockerfile"]),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(output.status.success());
+    // Selectors match the vendored package, not the directory name
+    assert!(!output_folder.join("hex/README.md").exists());
+    assert!(output_folder.join("hex-0.3.2/README.md").exists());
+    assert!(!output_folder.join("hex-0.3.2/Dockerfile").exists());
+}